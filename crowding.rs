@@ -0,0 +1,179 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import std::sort;
+import gtfs::{feed, trip, file_iter};
+import fleet::{mode_name};
+import report::{csv_row};
+
+export capacities, default_capacities, load_capacities, capacity_of,
+       stop_load, load_profile_csv, crowding_csv;
+
+/*
+ * how many people fit on a vehicle, and how full the vehicles got. a
+ * trip's capacity is its own if set, else its route's, else its mode's.
+ */
+
+/* load factors above this count as crowded */
+const crowded_factor : float = 0.85;
+
+type capacities = {
+    modes: map::hashmap<str, uint>,
+    routes: map::hashmap<str, uint>,
+    trips: map::hashmap<str, uint>
+};
+
+/* typical vehicles, seated and standing */
+fn default_capacities() -> capacities {
+    let modes = map::str_hash();
+    for vec::each([ ("tram", 200u), ("subway", 900u), ("rail", 1000u), ("bus", 80u),
+                    ("ferry", 300u), ("cable_car", 60u), ("gondola", 8u), ("funicular", 100u) ]) { |mc|
+        let (mode, cap) = mc;
+        modes.insert(mode, cap);
+    }
+    { modes: modes, routes: map::str_hash(), trips: map::str_hash() }
+}
+
+/* the defaults, overridden from a CSV file with a capacity column and
+   one of mode, route_id or trip_id on each row */
+fn load_capacities(path: str) -> capacities {
+    let c = default_capacities();
+    enum req { capacity }
+    enum opt { mode, route_id, trip_id }
+    let reqf = [ (capacity as uint, "capacity") ];
+    let optf = [
+        (mode as uint, "mode"),
+        (route_id as uint, "route_id"),
+        (trip_id as uint, "trip_id")
+            ];
+    file_iter(path, reqf, optf) { |row, req, opt|
+        let cap = alt uint::from_str(row[req[capacity as uint]]) {
+            some(n) { n }
+            none { fail(#fmt("%s: bad capacity: %s", path, row[req[capacity as uint]])) }
+        };
+        let mut set = false;
+        for vec::each([ (trip_id, c.trips), (route_id, c.routes), (mode, c.modes) ]) { |om|
+            let (o, m) = om;
+            alt opt[o as uint] {
+                some(n) if !set && row[n] != "" {
+                    m.insert(row[n], cap);
+                    set = true;
+                }
+                _ {}
+            }
+        }
+        if !set {
+            fail(#fmt("%s: capacity for no mode, route or trip", path));
+        }
+    };
+    ret c;
+}
+
+fn capacity_of(c: capacities, feed: feed, trip: @trip) -> uint {
+    alt c.trips.find(trip.id) {
+        some(n) { ret n; }
+        none {}
+    }
+    alt c.routes.find(trip.route_id) {
+        some(n) { ret n; }
+        none {}
+    }
+    let mode = mode_name(feed.routes.get(trip.route_id).route_type);
+    alt c.modes.find(mode) {
+        some(n) { n }
+        none { uint::max_value }
+    }
+}
+
+/* what happened at one stop of one trip; `load` is the number aboard
+   leaving it */
+type stop_load = {
+    trip_id: str,
+    route_id: str,
+    stop_id: str,
+    sequence: uint,
+    time: uint,
+    boarded: uint,
+    alighted: uint,
+    /* turned away for want of room */
+    denied: uint,
+    load: uint,
+    capacity: uint
+};
+
+fn load_factor(l: stop_load) -> float {
+    if l.capacity == 0u { 0. } else { (l.load as float) / (l.capacity as float) }
+}
+
+/* each trip's loads stop by stop */
+fn load_profile_csv(loads: [ stop_load ]) -> str {
+    let sorted = sort::merge_sort({|a, b|
+        a.trip_id < b.trip_id || (a.trip_id == b.trip_id && a.sequence <= b.sequence)
+    }, loads);
+    let mut res = csv_row([ "trip_id", "route_id", "stop_id", "stop_sequence", "time", "boarded",
+                            "alighted", "denied", "load", "capacity", "load_factor" ]) + "\n";
+    for vec::each(sorted) { |l|
+        res += csv_row([ l.trip_id, l.route_id, l.stop_id, uint::str(l.sequence),
+                         gtfs::time_format(l.time), uint::str(l.boarded), uint::str(l.alighted),
+                         uint::str(l.denied), uint::str(l.load), uint::str(l.capacity),
+                         #fmt("%.2f", load_factor(l)) ]) + "\n";
+    }
+    ret res;
+}
+
+/* per route: boardings, denied boardings, the fullest any vehicle got,
+   its mean load factor, and how many stop-to-stop segments were crowded */
+fn crowding_csv(loads: [ stop_load ]) -> str {
+    type stats = {
+        trips: map::set<str>,
+        mut boarded: uint,
+        mut denied: uint,
+        mut peak_load: uint,
+        mut peak_factor: float,
+        mut factor_sum: float,
+        mut segments: uint,
+        mut crowded: uint
+    };
+    let routes : map::hashmap<str, @stats> = map::str_hash();
+    for vec::each(loads) { |l|
+        let s = alt routes.find(l.route_id) {
+            some(s) { s }
+            none {
+                let s = @{ trips: map::str_hash(), mut boarded: 0u, mut denied: 0u,
+                           mut peak_load: 0u, mut peak_factor: 0., mut factor_sum: 0.,
+                           mut segments: 0u, mut crowded: 0u };
+                routes.insert(l.route_id, s);
+                s
+            }
+        };
+        map::set_add(s.trips, l.trip_id);
+        s.boarded += l.boarded;
+        s.denied += l.denied;
+        s.peak_load = uint::max(s.peak_load, l.load);
+        let f = load_factor(l);
+        s.peak_factor = float::fmax(s.peak_factor, f);
+        /* the mean is over segments with anyone aboard */
+        if l.load > 0u {
+            s.factor_sum += f;
+            s.segments += 1u;
+            if f > crowded_factor {
+                s.crowded += 1u;
+            }
+        }
+    }
+    let mut ids = [];
+    for routes.each_key() { |id| ids += [ id ]; }
+    let mut res = csv_row([ "route_id", "trips", "boarded", "denied", "peak_load",
+                            "peak_load_factor", "mean_load_factor", "crowded_segments" ]) + "\n";
+    for vec::each(sort::merge_sort({|a, b| a <= b }, ids)) { |id|
+        let s = routes.get(id);
+        let mut ntrips = 0u;
+        for s.trips.each_key() { |_t| ntrips += 1u; }
+        let mean = if s.segments == 0u { 0. } else { s.factor_sum / (s.segments as float) };
+        res += csv_row([ id, uint::str(ntrips), uint::str(s.boarded), uint::str(s.denied),
+                         uint::str(s.peak_load), #fmt("%.2f", s.peak_factor), #fmt("%.2f", mean),
+                         uint::str(s.crowded) ]) + "\n";
+    }
+    ret res;
+}
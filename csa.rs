@@ -0,0 +1,316 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import std::sort;
+import gtfs::{feed, feedaccess, date, weekday_of};
+import transfer::{transfers, paths_from};
+
+export connection, timetable, build_timetable, stop_lookup,
+       profile_to, profile_between, profile_at, earliest_arrivals;
+
+/*
+ * the connection scan algorithm (Dibbelt, Pajor, Strasser & Wagner, 2013).
+ * every hop of every active trip between consecutive stops is a
+ * connection, and the whole day is one array of them sorted by departure.
+ * stops and trips are numbered, and nothing here is task-local, so a
+ * timetable can be sent to other tasks.
+ */
+
+type connection = {
+    dep_stop: uint,
+    arr_stop: uint,
+    dep_time: uint,
+    arr_time: uint,
+    trip: uint,
+    /* position within the trip, to keep a trip's connections in order */
+    hop: uint,
+    /* can board at dep_stop, can alight at arr_stop */
+    pickup: bool,
+    drop_off: bool
+};
+
+type timetable = {
+    date: date,
+    stop_ids: [ str ],
+    trip_ids: [ str ],
+    /* by departure time */
+    connections: [ connection ],
+    /* footpaths from each stop: (to, seconds) */
+    footpaths: [ [ (uint, uint) ] ]
+};
+
+fn build_timetable(feed: feed, date: date, footpaths: transfers) -> timetable {
+    let stop_index : map::hashmap<str, uint> = map::str_hash();
+    let mut stop_ids = [];
+    for feed.stops.each_key() { |id|
+        stop_index.insert(id, vec::len(stop_ids));
+        stop_ids += [ id ];
+    }
+
+    let service_ids = feed.active_service_ids(weekday_of(date), date);
+    let trip_ids = sort::merge_sort({|a, b| a <= b }, feed.trip_ids_for_service_ids(service_ids));
+    let mut conns = [mut];
+    for vec::eachi(trip_ids) { |t, trip_id|
+        let times = alt feed.stop_times.find(trip_id) {
+            some(times) { times }
+            none { cont; }
+        };
+        let mut i = 1u;
+        while i < vec::len(times) {
+            let a = times[i - 1u], b = times[i];
+            conns += [ {
+                dep_stop: stop_index.get(a.stop_id),
+                arr_stop: stop_index.get(b.stop_id),
+                dep_time: a.departure_time,
+                arr_time: b.arrival_time,
+                trip: t,
+                hop: i - 1u,
+                pickup: marshal_allowed(a.pickup_type),
+                drop_off: marshal_allowed(b.drop_off_type)
+            } ];
+            i += 1u;
+        }
+    }
+    /* ties are broken by arrival, trip and position in the trip, so that
+       consecutive connections of a trip in the same second - zero-length
+       hops between timepoints - stay in order */
+    sort::quick_sort3({|a, b|
+        (a.dep_time, a.arr_time, a.trip, a.hop) < (b.dep_time, b.arr_time, b.trip, b.hop)
+    }, {|a, b|
+        a.dep_time == b.dep_time && a.arr_time == b.arr_time && a.trip == b.trip && a.hop == b.hop
+    }, conns);
+
+    let walks = vec::map(stop_ids) { |id|
+        let mut v = [];
+        for vec::each(paths_from(footpaths, id)) { |t|
+            alt stop_index.find(t.to) {
+                some(to) { v += [ (to, t.secs) ]; }
+                none {}
+            }
+        }
+        v
+    };
+
+    {
+        date: date,
+        stop_ids: stop_ids,
+        trip_ids: trip_ids,
+        connections: vec::from_mut(conns),
+        footpaths: walks
+    }
+}
+
+fn stop_lookup(tt: timetable) -> map::hashmap<str, uint> {
+    let m : map::hashmap<str, uint> = map::str_hash();
+    for vec::eachi(tt.stop_ids) { |i, id|
+        m.insert(id, i);
+    }
+    ret m;
+}
+
+/* adds (dep, arr) to a profile unless something already there departs no
+   earlier and arrives no later, dropping anything it beats. profiles are
+   kept by departure, latest first */
+fn profile_insert(p: [ (uint, uint) ], dep: uint, arr: uint) -> [ (uint, uint) ] {
+    let mut res = [];
+    let mut placed = false;
+    for vec::each(p) { |e|
+        let (d, a) = e;
+        if d >= dep && a <= arr {
+            ret p;
+        }
+        if dep >= d && arr <= a {
+            cont;
+        }
+        if !placed && dep > d {
+            res += [ (dep, arr) ];
+            placed = true;
+        }
+        res += [ e ];
+    }
+    if !placed {
+        res += [ (dep, arr) ];
+    }
+    ret res;
+}
+
+/* earliest arrival leaving at `time` or later, by a profile */
+fn profile_at(p: [ (uint, uint) ], time: uint) -> uint {
+    let mut best = uint::max_value;
+    for vec::each(p) { |e|
+        let (d, a) = e;
+        if d < time {
+            break;
+        }
+        best = uint::min(best, a);
+    }
+    ret best;
+}
+
+/* for every stop, the Pareto-optimal (departure, arrival) pairs for getting
+   to `target` during the day, latest departure first. `change` is the time
+   allowed for changing vehicles at a stop */
+fn profile_to(tt: timetable, target: uint, change: uint) -> [ [ (uint, uint) ] ] {
+    let never = uint::max_value;
+    let n = vec::len(tt.stop_ids);
+
+    /* walking to the target at the end, and footpaths into each stop */
+    let mut final_walk = vec::to_mut(vec::from_elem(n, never));
+    final_walk[target] = 0u;
+    let mut incoming = vec::to_mut(vec::from_elem(n, []));
+    for vec::eachi(tt.footpaths) { |from, fps|
+        for vec::each(fps) { |fp|
+            let (to, secs) = fp;
+            incoming[to] += [ (from, secs) ];
+            if to == target {
+                final_walk[from] = uint::min(final_walk[from], secs);
+            }
+        }
+    }
+
+    let mut profiles = vec::to_mut(vec::from_elem(n, []));
+    let mut trip_arrival = vec::to_mut(vec::from_elem(vec::len(tt.trip_ids), never));
+    let mut i = vec::len(tt.connections);
+    while i > 0u {
+        i -= 1u;
+        let c = tt.connections[i];
+        let mut best = trip_arrival[c.trip];
+        if c.drop_off {
+            if final_walk[c.arr_stop] != never {
+                best = uint::min(best, c.arr_time + final_walk[c.arr_stop]);
+            }
+            best = uint::min(best, profile_at(profiles[c.arr_stop], c.arr_time + change));
+        }
+        trip_arrival[c.trip] = best;
+        if best == never || !c.pickup {
+            cont;
+        }
+        profiles[c.dep_stop] = profile_insert(profiles[c.dep_stop], c.dep_time, best);
+        for vec::each(incoming[c.dep_stop]) { |fp|
+            let (from, secs) = fp;
+            if c.dep_time >= secs && from != target {
+                profiles[from] = profile_insert(profiles[from], c.dep_time - secs, best);
+            }
+        }
+    }
+    ret vec::from_mut(profiles);
+}
+
+/* the profile from one stop to another, earliest departure first */
+fn profile_between(tt: timetable, source: uint, target: uint, change: uint) -> [ (uint, uint) ] {
+    ret vec::reversed(profile_to(tt, target, change)[source]);
+}
+
+/* index of the first connection departing at or after `time` */
+fn first_connection(tt: timetable, time: uint) -> uint {
+    let mut lo = 0u, hi = vec::len(tt.connections);
+    while lo < hi {
+        let mid = (lo + hi) / 2u;
+        if tt.connections[mid].dep_time < time {
+            lo = mid + 1u;
+        } else {
+            hi = mid;
+        }
+    }
+    ret lo;
+}
+
+/* earliest arrival at every stop, starting from the `sources`, each a
+   (stop, time) pair; uint::max_value where a stop can't be reached by
+   `until`. `change` is the time allowed for changing vehicles */
+fn earliest_arrivals(tt: timetable, sources: [ (uint, uint) ], until: uint, change: uint) -> [ uint ] {
+    let never = uint::max_value;
+    let n = vec::len(tt.stop_ids);
+    let arrival = vec::to_mut(vec::from_elem(n, never));
+    /* whether the arrival was on a vehicle, so boarding another needs time */
+    let by_vehicle = vec::to_mut(vec::from_elem(n, false));
+    let on_trip = vec::to_mut(vec::from_elem(vec::len(tt.trip_ids), false));
+    let mut start = never;
+    for vec::each(sources) { |src|
+        let (s, t) = src;
+        if t < arrival[s] {
+            arrival[s] = t;
+            start = uint::min(start, t);
+        }
+    }
+    if start == never {
+        ret vec::from_mut(arrival);
+    }
+    /* footpaths from the sources */
+    for vec::each(sources) { |src|
+        let (s, _) = src;
+        for vec::each(tt.footpaths[s]) { |fp|
+            let (q, secs) = fp;
+            if arrival[s] + secs < arrival[q] {
+                arrival[q] = arrival[s] + secs;
+            }
+        }
+    }
+
+    let mut i = first_connection(tt, start);
+    while i < vec::len(tt.connections) {
+        let c = tt.connections[i];
+        i += 1u;
+        if c.dep_time > until {
+            break;
+        }
+        if !on_trip[c.trip] {
+            let a = arrival[c.dep_stop];
+            if a == never || !c.pickup {
+                cont;
+            }
+            let ready = if by_vehicle[c.dep_stop] { a + change } else { a };
+            if ready > c.dep_time {
+                cont;
+            }
+            on_trip[c.trip] = true;
+        }
+        if c.drop_off && c.arr_time < arrival[c.arr_stop] {
+            arrival[c.arr_stop] = c.arr_time;
+            by_vehicle[c.arr_stop] = true;
+            for vec::each(tt.footpaths[c.arr_stop]) { |fp|
+                let (q, secs) = fp;
+                if c.arr_time + secs < arrival[q] {
+                    arrival[q] = c.arr_time + secs;
+                    by_vehicle[q] = false;
+                }
+            }
+        }
+    }
+    ret vec::from_mut(arrival);
+}
+
+#[cfg(test)]
+mod tests {
+    import csa::{timetable, build_timetable, stop_lookup, profile_between, earliest_arrivals};
+    import transfer::{new_transfers};
+    import testfeed::{test_feed, test_date, add_stop, add_trip};
+
+    /* from a to d: 08:00 in at 09:00, 08:10 in at 08:50, and 08:20 in at
+       09:10. the 08:00 is beaten by the 08:10 on both counts */
+    fn three_trips() -> timetable {
+        let f = test_feed();
+        for vec::each([ "a", "d" ]) { |id| add_stop(f, id, 0., 0.); }
+        add_trip(f, "r", "r-1", [ ("a", 28800u, 28800u), ("d", 32400u, 32400u) ]);
+        add_trip(f, "r", "r-2", [ ("a", 29400u, 29400u), ("d", 31800u, 31800u) ]);
+        add_trip(f, "r", "r-3", [ ("a", 30000u, 30000u), ("d", 33000u, 33000u) ]);
+        build_timetable(f, test_date(), new_transfers())
+    }
+
+    #[test]
+    fn profile_drops_dominated_trips() {
+        let tt = three_trips();
+        let stops = stop_lookup(tt);
+        let p = profile_between(tt, stops.get("a"), stops.get("d"), 60u);
+        assert(p == [ (29400u, 31800u), (30000u, 33000u) ]);
+    }
+
+    #[test]
+    fn earliest_arrival_takes_the_overtaking_trip() {
+        let tt = three_trips();
+        let stops = stop_lookup(tt);
+        let arrivals = earliest_arrivals(tt, [ (stops.get("a"), 28500u) ], 36000u, 60u);
+        assert(arrivals[stops.get("d")] == 31800u);
+    }
+}
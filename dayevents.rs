@@ -1,9 +1,15 @@
 
 use gtfs;
 use std;
-import std::sort;
-import gtfs::gtfs_load;
+import std::map;
+import std::map::{map};
+import gtfs::{gtfs_load_tables, gtfs_load_stop_times};
 import gtfs::{feedaccess};
+import gtfs::daysim::{observer, day_trips, run_day};
+import gtfs::fleet::{blocks, peak, peak_fleet};
+import gtfs::positions::{position, fleet, positions_at};
+import gtfs::rng::{new_rng};
+import gtfs::delay::{delays, no_delays, default_delays};
 
 enum event {
     startevents(uint,uint),
@@ -11,9 +17,49 @@ enum event {
     starttrip(uint, ~gtfs::trip),
     endtrip(uint, ~gtfs::trip),
     stoparrival(uint, ~gtfs::trip, ~gtfs::stop_time),
+    /* the last field is the dwell: seconds since arriving */
+    stopdeparture(uint, ~gtfs::trip, ~gtfs::stop_time, uint),
+    vehiclepositions(uint, [ position ]),
+    /* a vehicle done with one trip of its block, waiting this many seconds
+       to start the next */
+    layover(uint, str, ~gtfs::trip, ~gtfs::trip, uint),
+    /* peak vehicles by agency, then by route */
+    peakfleet([ peak ], [ peak ]),
 }
 
-fn simulate_events(out: comm::chan<event>, agency_id: str, dstr: str, data_dir: str) {
+/* passes what happens on down the channel */
+type sender = {
+    feed: gtfs::feed,
+    out: comm::chan<event>
+};
+
+impl of observer for sender {
+    fn trip_started(now: uint, trip: @gtfs::trip) {
+        comm::send(self.out, starttrip(now, ~*trip));
+    }
+    fn arrived(now: uint, trip: @gtfs::trip, st: @gtfs::stop_time) -> (uint, uint) {
+        comm::send(self.out, stoparrival(now, ~*trip, ~*st));
+        (0u, 0u)
+    }
+    fn departed(now: uint, trip: @gtfs::trip, st: @gtfs::stop_time, dwell: uint) {
+        comm::send(self.out, stopdeparture(now, ~*trip, ~*st, dwell));
+    }
+    fn trip_ended(now: uint, trip: @gtfs::trip) {
+        comm::send(self.out, endtrip(now, ~*trip));
+    }
+    fn layover(now: uint, block_id: str, trip: @gtfs::trip, next: @gtfs::trip, secs: uint) {
+        comm::send(self.out, layover(now, block_id, ~*trip, ~*next, secs));
+    }
+    fn snapshot(now: uint, running: fleet) {
+        comm::send(self.out, vehiclepositions(now, positions_at(self.feed, running, now)));
+    }
+}
+
+/* with a snapshot interval, vehicle positions are sent every that many
+   seconds while trips are running. times are as the delay model has them;
+   the stop times sent are as scheduled */
+fn simulate_events(out: comm::chan<event>, agency_id: str, dstr: str, data_dir: str,
+                   snapshot_every: option<uint>, delays: delays) {
     let tm = alt std::time::strptime(dstr, "%a %Y-%m-%d") {
         result::ok(d) { d }
         result::err(s) { fail(s) }
@@ -36,85 +82,32 @@ fn simulate_events(out: comm::chan<event>, agency_id: str, dstr: str, data_dir:
 
     io::println(#fmt("%? %?", day, date));
 
-    let feed = gtfs_load(data_dir);
+    let feed = gtfs_load_tables(data_dir);
 
-    let mut trip_stops = { ||
-        let service_ids = feed.active_service_ids(day, date);
-        let trip_ids = feed.trip_ids_for_service_ids(service_ids);
-        let trips = vec::filter(feed.lookup_trips(trip_ids)) { |trip|
-            let route = feed.lookup_routes([trip.route_id])[0];
-            route.agency_id == agency_id
-        };
-        comm::send(out, startevents(vec::len(service_ids), vec::len(trips)));
-        let mut ts = [mut];
-        for vec::each(trips) { |trip|
-            let st = feed.lookup_stop_times([trip.id])[0];
-            let first_arrival = st[0].arrival_time;
-            ts += [ (trip, first_arrival, st) ];
-        }
-        sort::quick_sort3({|v1,v2| 
-            let (_, a, _) = v1;
-            let (_, b, _) = v2;
-            a < b
-        }, {|v1,v2|
-            let (_, a, _) = v1;
-            let (_, b, _) = v2;
-            a == b
-        }, ts);
-        ts
-    }();
-    if vec::len(trip_stops) == 0u {
-        ret;
+    let service_ids = feed.active_service_ids(day, date);
+    let trips = vec::filter(feed.lookup_trips(feed.trip_ids_for_service_ids(service_ids))) { |trip|
+        let route = feed.lookup_routes([trip.route_id])[0];
+        route.agency_id == agency_id
+    };
+    /* only keep stop times for the trips we're going to run */
+    let wanted : map::set<str> = map::str_hash();
+    for vec::each(trips) { |trip|
+        map::set_add(wanted, trip.id);
     }
-    let mut (_, now, _) = trip_stops[0];
-    let mut trip_index = 0u;
-    type trip_run = {
-        trip: @gtfs::trip,
-        stop_times: [ mut @gtfs::stop_time ],
-        mut offset: uint
+    gtfs_load_stop_times(feed, data_dir) { |trip_id| wanted.contains_key(trip_id) };
+    comm::send(out, startevents(vec::len(service_ids), vec::len(trips)));
+
+    /* trips the feed has no stop times for can't be run */
+    let trip_ids = vec::filter(vec::map(trips) { |trip| trip.id }) { |id|
+        feed.stop_times.contains_key(id)
     };
+    /* trips sharing a block are run by one vehicle, one after another */
+    let duties = blocks(feed, trip_ids);
+    let (by_agency, by_route) = peak_fleet(feed, duties);
+    comm::send(out, peakfleet(by_agency, by_route));
 
-    let mut running : [ @trip_run ] = [];
-    loop {
-        let mut next_time = uint::max_value;
-        /* find commencing trips */
-        while trip_index < vec::len(trip_stops) {
-            let (trip, first_arrival, stop_times) = trip_stops[trip_index];
-            assert(first_arrival >= now);
-            if first_arrival != now {
-                next_time = uint::min(first_arrival, next_time);
-                break;
-            }
-            running += [ @{ trip: trip, stop_times: stop_times, offset: 0u } ];
-            comm::send(out, starttrip(now, ~*trip));
-            trip_index += 1u;
-        }
-        /* find stop arrivals & ending trips */
-        let mut still_running = [];
-        for vec::each(running) { |r|
-            while r.offset < vec::len(r.stop_times) {
-                let st = r.stop_times[r.offset];
-                assert(st.arrival_time >= now);
-                if st.arrival_time != now {
-                    next_time = uint::min(st.arrival_time, next_time);
-                    break;
-                }
-                comm::send(out, stoparrival(now, ~*r.trip, ~*st));
-                r.offset += 1u;
-            }
-            if r.offset == vec::len(r.stop_times) {
-                comm::send(out, endtrip(now, ~*r.trip));
-            } else {
-                still_running += [ r ];
-            }
-        }
-        running = still_running;
-        if vec::len(running) == 0u && trip_index == vec::len(trip_stops) {
-            break;
-        }
-        /* tick */
-        now = next_time;
-    }
+    let events = { feed: feed, out: out };
+    run_day(feed, day_trips(feed, trip_ids), duties, [ events as observer ], delays, snapshot_every);
     comm::send(out, endevents);
 }
 
@@ -126,7 +119,17 @@ fn main(args: [str])
         let agency_id = args[1];
         let dstr = args[2];
         let data_dir = args[3];
-        simulate_events(chan, agency_id, dstr, data_dir);
+        let snapshot_every = if vec::len(args) > 4u { uint::from_str(args[4]) } else { none };
+        /* with a seed, vehicles run late, reproducibly */
+        let delays = if vec::len(args) > 5u {
+            alt uint::from_str(args[5]) {
+                some(seed) { default_delays(new_rng(seed as u64), map::str_hash()) }
+                none { fail(#fmt("bad seed: %s", args[5])) }
+            }
+        } else {
+            no_delays()
+        };
+        simulate_events(chan, agency_id, dstr, data_dir, snapshot_every, delays);
     }
     loop {
         let result = comm::recv(port);
@@ -147,6 +150,23 @@ fn main(args: [str])
             stoparrival(t, trip, stop) {
                 log(error, ("stoparrival", t, trip, stop))
             }
+            stopdeparture(t, trip, stop, dwell) {
+                log(error, ("stopdeparture", t, trip, stop, dwell))
+            }
+            vehiclepositions(t, positions) {
+                log(error, ("vehiclepositions", t, positions))
+            }
+            layover(t, block_id, trip, next, secs) {
+                log(error, ("layover", t, block_id, trip, next, secs))
+            }
+            peakfleet(by_agency, by_route) {
+                for vec::each(by_agency) { |p|
+                    io::println(#fmt("agency %s: %u vehicles at %s", p.key, p.vehicles, gtfs::time_format(p.at)));
+                }
+                for vec::each(by_route) { |p|
+                    io::println(#fmt("route %s: %u vehicles at %s", p.key, p.vehicles, gtfs::time_format(p.at)));
+                }
+            }
         }
     }
 }
@@ -0,0 +1,200 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import std::sort;
+import gtfs::{feed, feedaccess, trip, stop_time};
+import sim::{new_sim, schedule, run, pending};
+import fleet::{duty};
+import positions::{fleet, new_fleet, trip_started, trip_retimed, trip_ended};
+import delay::{delays};
+
+export observer, day_trips, run_day;
+
+/*
+ * a day of vehicles running their trips, as a discrete-event simulation.
+ * vehicles wait at each stop from arrival to departure, and vehicles on a
+ * block lay over between its trips. how long things really take is up to
+ * a delay model: a late vehicle stays late along its trip, and starts the
+ * next trip of its block late if its layover can't absorb it. whatever
+ * wants to know what happens - a log, passengers - is an observer.
+ * observers are given the scheduled stop time with the actual time.
+ */
+
+iface observer {
+    fn trip_started(now: uint, trip: @trip);
+    /* returns how many got on and off */
+    fn arrived(now: uint, trip: @trip, st: @stop_time) -> (uint, uint);
+    /* `dwell` is the seconds since arriving */
+    fn departed(now: uint, trip: @trip, st: @stop_time, dwell: uint);
+    fn trip_ended(now: uint, trip: @trip);
+    /* the vehicle which ran `trip` will run `next` in `secs` seconds */
+    fn layover(now: uint, block_id: str, trip: @trip, next: @trip, secs: uint);
+    /* every snapshot interval, with the trips running */
+    fn snapshot(now: uint, running: fleet);
+}
+
+/* what the simulation queues; uints index the trips, then stop times */
+enum day_event {
+    /* a trip's scheduled start */
+    trip_due(uint),
+    trip_begins(uint),
+    reaches(uint, uint),
+    leaves(uint, uint),
+    snapshot
+}
+
+/* the given trips with their stop times, in order of first arrival.
+   trips without stop times loaded are left out */
+fn day_trips(feed: feed, trip_ids: [ str ]) -> [ (@trip, [ mut @stop_time ]) ] {
+    let mut ts = [mut];
+    for vec::each(trip_ids) { |id|
+        alt feed.stop_times.find(id) {
+            some(times) if vec::len(times) > 0u {
+                ts += [ (feed.trips.get(id), times) ];
+            }
+            _ {}
+        }
+    }
+    let first = { |v: (@trip, [ mut @stop_time ])| let (_, st) = v; st[0].arrival_time };
+    sort::quick_sort3({|a, b| first(a) < first(b) }, {|a, b| first(a) == first(b) }, ts);
+    ret vec::from_mut(ts);
+}
+
+/* moves the times from stop `from` on by the difference between `at` and
+   stop `from`'s scheduled arrival: where a vehicle that late will be */
+fn project(scheduled: [ mut @stop_time ], actual: [ mut @stop_time ], from: uint, at: uint) {
+    let base = scheduled[from].arrival_time;
+    let shift = { |t: uint| if at >= base { t + (at - base) } else { t - uint::min(t, base - at) } };
+    let mut k = from;
+    while k < vec::len(scheduled) {
+        let st = scheduled[k];
+        actual[k] = @{ arrival_time: shift(st.arrival_time), departure_time: shift(st.departure_time) with *st };
+        k += 1u;
+    }
+}
+
+/* runs the trips (as from day_trips), telling each observer what happens.
+   trips sharing a duty are run by one vehicle. with a snapshot interval,
+   observers get a snapshot every that many seconds while trips run */
+fn run_day(feed: feed, trips: [ (@trip, [ mut @stop_time ]) ], duties: [ duty ],
+           observers: [ observer ], delays: delays, snapshot_every: option<uint>) {
+    if vec::len(trips) == 0u {
+        ret;
+    }
+    let index : map::hashmap<str, uint> = map::str_hash();
+    for uint::range(0u, vec::len(trips)) { |i|
+        let (trip, _) = trips[i];
+        index.insert(trip.id, i);
+    }
+    /* the next trip of each trip's block, if it's running today */
+    let never = uint::max_value;
+    let mut next_in_block = vec::to_mut(vec::from_elem(vec::len(trips), ("", never)));
+    let mut has_prev = vec::to_mut(vec::from_elem(vec::len(trips), false));
+    for vec::each(duties) { |d|
+        let mut i = 1u;
+        while i < vec::len(d.trip_ids) {
+            alt (index.find(d.trip_ids[i - 1u]), index.find(d.trip_ids[i])) {
+                (some(a), some(b)) {
+                    next_in_block[a] = (d.id, b);
+                    has_prev[b] = true;
+                }
+                _ {}
+            }
+            i += 1u;
+        }
+    }
+    /* each trip's times as run so far, and as they'll be from here on if
+       it loses no more time */
+    let mut actual = [];
+    for vec::each(trips) { |ts|
+        let (_, times) = ts;
+        let mut copy = [mut];
+        let mut k = 0u;
+        while k < vec::len(times) {
+            copy += [mut times[k]];
+            k += 1u;
+        }
+        actual += [ copy ];
+    }
+
+    /* each trip's events are scheduled one at a time as it goes, and each
+       trip's scheduled start queues the next trip's, so the queue only
+       ever holds what's running. a trip later in a block starts when its
+       vehicle is ready for it instead */
+    let (_, times) = trips[0];
+    let first = times[0].arrival_time;
+    let s = new_sim::<day_event>(first);
+    let running = new_fleet();
+    schedule(s, first, trip_due(0u));
+    if option::is_some(snapshot_every) {
+        schedule(s, first, snapshot);
+    }
+    run(s, none) { |now, ev|
+        alt ev {
+            trip_due(i) {
+                if !has_prev[i] {
+                    schedule(s, now, trip_begins(i));
+                }
+                if i + 1u < vec::len(trips) {
+                    let (_, next) = trips[i + 1u];
+                    schedule(s, next[0].arrival_time, trip_due(i + 1u));
+                }
+            }
+            trip_begins(i) {
+                let (trip, stop_times) = trips[i];
+                project(stop_times, actual[i], 0u, now);
+                trip_started(running, trip, actual[i]);
+                for vec::each(observers) { |o| o.trip_started(now, trip); }
+                schedule(s, now, reaches(i, 0u));
+            }
+            reaches(i, offset) {
+                let (trip, stop_times) = trips[i];
+                let st = stop_times[offset];
+                let mut boarded = 0u;
+                let mut alighted = 0u;
+                for vec::each(observers) { |o|
+                    let (on, off) = o.arrived(now, trip, st);
+                    boarded += on;
+                    alighted += off;
+                }
+                let dwell = delays.dwell.dwell_secs(trip, st, boarded, alighted);
+                let depart = uint::max(now + dwell, st.departure_time);
+                actual[i][offset] = @{ arrival_time: now, departure_time: depart with *st };
+                trip_retimed(running, trip, actual[i]);
+                schedule(s, depart, leaves(i, offset));
+            }
+            leaves(i, offset) {
+                let (trip, stop_times) = trips[i];
+                let st = stop_times[offset];
+                let arrived = actual[i][offset].arrival_time;
+                for vec::each(observers) { |o| o.departed(now, trip, st, now - arrived); }
+                if offset + 1u == vec::len(stop_times) {
+                    trip_ended(running, trip);
+                    for vec::each(observers) { |o| o.trip_ended(now, trip); }
+                    let (block_id, j) = next_in_block[i];
+                    if j != never {
+                        let (next, next_times) = trips[j];
+                        let start = uint::max(next_times[0].arrival_time, now + delays.min_layover);
+                        for vec::each(observers) { |o| o.layover(now, block_id, trip, next, start - now); }
+                        schedule(s, start, trip_begins(j));
+                    }
+                } else {
+                    let next = stop_times[offset + 1u];
+                    let secs = delays.running.running_secs(trip, st, next)
+                        + delays.incidents.incident_secs(trip, st, next);
+                    project(stop_times, actual[i], offset + 1u, now + secs);
+                    trip_retimed(running, trip, actual[i]);
+                    schedule(s, now + secs, reaches(i, offset + 1u));
+                }
+            }
+            snapshot {
+                for vec::each(observers) { |o| o.snapshot(now, running); }
+                /* carry on while anything else is left to happen */
+                if pending(s) > 0u {
+                    schedule(s, now + option::get(snapshot_every), snapshot);
+                }
+            }
+        }
+    };
+}
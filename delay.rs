@@ -0,0 +1,179 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::{trip, stop_time, file_iter};
+import rng::{rng, fork, uniform, lognormal, exponential};
+
+export running_model, dwell_model, incident_model, delays, no_delays,
+       spread, lognormal_running, load_segment_spreads, boarding_dwell,
+       random_incidents, default_delays;
+
+/*
+ * how far real running strays from the timetable. a vehicle takes a
+ * model's time to run each segment between stops, stands at each stop for
+ * as long as its passengers take to get on and off, and now and then is
+ * held up by an incident. it never leaves a stop before its scheduled
+ * departure. models with randomness draw from their own generator, so a
+ * seed gives the same day every time.
+ */
+
+iface running_model {
+    /* seconds to run from one stop to the next */
+    fn running_secs(trip: @trip, from: @stop_time, to: @stop_time) -> uint;
+}
+
+iface dwell_model {
+    /* seconds standing at a stop while people get on and off */
+    fn dwell_secs(trip: @trip, st: @stop_time, boarded: uint, alighted: uint) -> uint;
+}
+
+iface incident_model {
+    /* seconds lost between two stops to something going wrong, if anything */
+    fn incident_secs(trip: @trip, from: @stop_time, to: @stop_time) -> uint;
+}
+
+type delays = {
+    running: running_model,
+    dwell: dwell_model,
+    incidents: incident_model,
+    /* the least time at the terminal between trips of a block */
+    min_layover: uint
+};
+
+fn scheduled_secs(from: @stop_time, to: @stop_time) -> uint {
+    if to.arrival_time > from.departure_time { to.arrival_time - from.departure_time } else { 0u }
+}
+
+/* everything runs to time */
+enum as_scheduled { as_scheduled }
+
+impl of running_model for as_scheduled {
+    fn running_secs(_trip: @trip, from: @stop_time, to: @stop_time) -> uint {
+        scheduled_secs(from, to)
+    }
+}
+
+impl of dwell_model for as_scheduled {
+    fn dwell_secs(_trip: @trip, _st: @stop_time, _boarded: uint, _alighted: uint) -> uint {
+        0u
+    }
+}
+
+impl of incident_model for as_scheduled {
+    fn incident_secs(_trip: @trip, _from: @stop_time, _to: @stop_time) -> uint {
+        0u
+    }
+}
+
+fn no_delays() -> delays {
+    {
+        running: as_scheduled as running_model,
+        dwell: as_scheduled as dwell_model,
+        incidents: as_scheduled as incident_model,
+        min_layover: 0u
+    }
+}
+
+/* a segment's running time as a multiple of the scheduled time: its
+   median, and sigma, the standard deviation of its log */
+type spread = {
+    median: float,
+    sigma: float
+};
+
+/* lognormal running times, with a spread for particular segments, keyed on
+   "from_stop_id to_stop_id", or the default */
+type lognormal_running = {
+    r: rng,
+    default: spread,
+    segments: map::hashmap<str, spread>
+};
+
+fn segment_key(from: str, to: str) -> str {
+    from + " " + to
+}
+
+impl of running_model for lognormal_running {
+    fn running_secs(_trip: @trip, from: @stop_time, to: @stop_time) -> uint {
+        let s = alt self.segments.find(segment_key(from.stop_id, to.stop_id)) {
+            some(s) { s }
+            none { self.default }
+        };
+        let secs = (scheduled_secs(from, to) as float) * lognormal(self.r, s.median, s.sigma);
+        float::floor(secs + 0.5) as uint
+    }
+}
+
+/* spreads from a CSV file with from_stop_id, to_stop_id, median and sigma
+   columns */
+fn load_segment_spreads(path: str) -> map::hashmap<str, spread> {
+    enum req { from, to, median, sigma }
+    let reqf = [
+        (from as uint, "from_stop_id"),
+        (to as uint, "to_stop_id"),
+        (median as uint, "median"),
+        (sigma as uint, "sigma")
+            ];
+    let num = { |s: str|
+        alt float::from_str(s) {
+            some(f) if f >= 0. { f }
+            _ { fail(#fmt("%s: bad number: %s", path, s)) }
+        }
+    };
+    let res = map::str_hash();
+    file_iter(path, reqf, []) { |row, req, _opt|
+        res.insert(segment_key(row[req[from as uint]], row[req[to as uint]]),
+                   { median: num(row[req[median as uint]]), sigma: num(row[req[sigma as uint]]) });
+    };
+    ret res;
+}
+
+/* doors open and shut, and each passenger takes a while */
+type boarding_dwell = {
+    fixed: float,
+    per_boarding: float,
+    per_alighting: float
+};
+
+impl of dwell_model for boarding_dwell {
+    fn dwell_secs(_trip: @trip, _st: @stop_time, boarded: uint, alighted: uint) -> uint {
+        if boarded == 0u && alighted == 0u {
+            ret 0u;
+        }
+        let secs = self.fixed + self.per_boarding * (boarded as float)
+            + self.per_alighting * (alighted as float);
+        float::ceil(secs) as uint
+    }
+}
+
+/* on each segment, some chance of a hold-up lasting an exponentially
+   distributed time */
+type random_incidents = {
+    r: rng,
+    chance: float,
+    mean_secs: float
+};
+
+impl of incident_model for random_incidents {
+    fn incident_secs(_trip: @trip, _from: @stop_time, _to: @stop_time) -> uint {
+        if uniform(self.r) >= self.chance {
+            ret 0u;
+        }
+        float::floor(exponential(self.r, self.mean_secs) + 0.5) as uint
+    }
+}
+
+/* modest variation everywhere, or as given for some segments. each model
+   gets its own generator forked from `r` */
+fn default_delays(r: rng, segments: map::hashmap<str, spread>) -> delays {
+    let running = { r: fork(r), default: { median: 1., sigma: 0.15 }, segments: segments };
+    let dwell = { fixed: 4., per_boarding: 3., per_alighting: 1.5 };
+    let incidents = { r: fork(r), chance: 0.002, mean_secs: 300. };
+    {
+        running: running as running_model,
+        dwell: dwell as dwell_model,
+        incidents: incidents as incident_model,
+        min_layover: 120u
+    }
+}
@@ -0,0 +1,249 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import std::sort;
+import std::json;
+import gtfs::{feed, feedaccess, point, rectangle, file_iter, gettime, time_format};
+import geom::{point_in_polygon};
+import passenger::{request};
+import rng::{rng, uniform, below, poisson};
+import report::{csv_row};
+
+export catchment, zones_from_stops, zones_from_geojson, od_flow, load_od,
+       generate_requests, requests_csv;
+
+/*
+ * passenger demand. an OD file gives how many trips go from each zone to
+ * each other in each time slice; the generator turns those into
+ * individual requests, leaving at random through their slice from a
+ * random stop of their zone to a random stop of the other. counts are
+ * poisson, so the same OD file gives a different day for each seed, and
+ * the same seed gives the same day.
+ */
+
+/* a zone and the stops people in it use */
+type catchment = {
+    id: str,
+    stop_ids: [ str ]
+};
+
+/* zones from the stops' zone_ids; stops without one are left out */
+fn zones_from_stops(feed: feed) -> [ catchment ] {
+    let members : map::hashmap<str, [ str ]> = map::str_hash();
+    let mut ids = [];
+    for feed.stops.each_value() { |stop|
+        alt stop.zone_id {
+            some(z) if z != "" {
+                let v = alt members.find(z) {
+                    some(v) { v }
+                    none { ids += [ z ]; [] }
+                };
+                members.insert(z, v + [ stop.id ]);
+            }
+            _ {}
+        }
+    }
+    vec::map(sort::merge_sort({|a, b| a <= b }, ids)) { |id|
+        { id: id, stop_ids: sort::merge_sort({|a, b| a <= b }, members.get(id)) }
+    }
+}
+
+fn json_field(j: json::json, key: str) -> option<json::json> {
+    alt j {
+        json::dict(d) { d.find(key) }
+        _ { none }
+    }
+}
+
+fn json_point(path: str, j: json::json) -> point {
+    alt j {
+        json::list(v) if vec::len(v) >= 2u {
+            alt (v[0], v[1]) {
+                (json::num(lon), json::num(lat)) { ret { lat: lat, lon: lon }; }
+                _ {}
+            }
+        }
+        _ {}
+    }
+    fail(#fmt("%s: bad coordinate", path));
+}
+
+fn json_list(path: str, j: json::json) -> [ json::json ] {
+    alt j {
+        json::list(v) { v }
+        _ { fail(#fmt("%s: expected a list", path)) }
+    }
+}
+
+/* a geometry's polygons, each a list of rings */
+fn json_polygons(path: str, geometry: json::json) -> [ [ [ point ] ] ] {
+    let coords = alt json_field(geometry, "coordinates") {
+        some(c) { c }
+        none { fail(#fmt("%s: geometry without coordinates", path)) }
+    };
+    let polygon = { |p: json::json|
+        vec::map(json_list(path, p)) { |ring|
+            vec::map(json_list(path, ring)) { |c| json_point(path, c) }
+        }
+    };
+    alt json_field(geometry, "type") {
+        some(json::string("Polygon")) { [ polygon(coords) ] }
+        some(json::string("MultiPolygon")) { vec::map(json_list(path, coords), polygon) }
+        _ { [] }
+    }
+}
+
+fn ring_bbox(ring: [ point ]) -> rectangle {
+    let mut sw = ring[0];
+    let mut ne = ring[0];
+    for vec::each(ring) { |pt|
+        sw = { lat: float::fmin(sw.lat, pt.lat), lon: float::fmin(sw.lon, pt.lon) };
+        ne = { lat: float::fmax(ne.lat, pt.lat), lon: float::fmax(ne.lon, pt.lon) };
+    }
+    { sw: sw, ne: ne }
+}
+
+/* zones from the Polygon and MultiPolygon features of a GeoJSON file,
+   named by the given property. a zone gets the stops inside it, or if
+   there are none, the stop nearest its first corner */
+fn zones_from_geojson(feed: feed, path: str, id_property: str) -> [ catchment ] {
+    io::println("loading file: " + path);
+    let text = alt io::read_whole_file_str(path) {
+        result::ok(s) { s }
+        result::err(e) { fail(#fmt("cannot open %s: %s", path, e)) }
+    };
+    let doc = alt json::from_str(text) {
+        result::ok(j) { j }
+        result::err(e) { fail(#fmt("%s:%u: %s", path, e.line, e.msg)) }
+    };
+    let features = alt json_field(doc, "features") {
+        some(f) { json_list(path, f) }
+        none { fail(#fmt("%s: not a feature collection", path)) }
+    };
+    let mut res = [];
+    for vec::each(features) { |f|
+        let id = alt json_field(f, "properties") {
+            some(props) {
+                alt json_field(props, id_property) {
+                    some(json::string(s)) { s }
+                    some(json::num(n)) { float::to_str(n, 0u) }
+                    _ { fail(#fmt("%s: feature without a %s", path, id_property)) }
+                }
+            }
+            none { fail(#fmt("%s: feature without properties", path)) }
+        };
+        let polygons = alt json_field(f, "geometry") {
+            some(g) { json_polygons(path, g) }
+            none { [] }
+        };
+        let polygons = vec::filter(polygons) { |p| vec::len(p) > 0u && vec::len(p[0]) > 0u };
+        if vec::len(polygons) == 0u {
+            cont;
+        }
+        let mut stop_ids = [];
+        for vec::each(polygons) { |p|
+            for vec::each(feed.stops_in_rect(ring_bbox(p[0]))) { |stop|
+                if point_in_polygon(stop.pt, p) && !vec::contains(stop_ids, stop.id) {
+                    stop_ids += [ stop.id ];
+                }
+            }
+        }
+        if vec::len(stop_ids) == 0u {
+            for vec::each(feed.nearest_stops(polygons[0][0][0], 1u)) { |sd|
+                let (stop, _) = sd;
+                stop_ids += [ stop.id ];
+            }
+        }
+        res += [ { id: id, stop_ids: sort::merge_sort({|a, b| a <= b }, stop_ids) } ];
+    }
+    ret res;
+}
+
+/* the trips expected from one zone to another leaving in [start, end) */
+type od_flow = {
+    origin: str,
+    destination: str,
+    start: uint,
+    end: uint,
+    trips: float
+};
+
+/* flows from a CSV file with origin_zone, destination_zone, start_time,
+   end_time and trips columns */
+fn load_od(path: str) -> [ od_flow ] {
+    enum req { origin, destination, start, end, trips }
+    let reqf = [
+        (origin as uint, "origin_zone"),
+        (destination as uint, "destination_zone"),
+        (start as uint, "start_time"),
+        (end as uint, "end_time"),
+        (trips as uint, "trips")
+            ];
+    let mut res = [];
+    file_iter(path, reqf, []) { |row, req, _opt|
+        let n = alt float::from_str(row[req[trips as uint]]) {
+            some(f) if f >= 0. { f }
+            _ { fail(#fmt("%s: bad trip count: %s", path, row[req[trips as uint]])) }
+        };
+        let s = gettime(row[req[start as uint]]);
+        let e = gettime(row[req[end as uint]]);
+        if e <= s {
+            fail(#fmt("%s: time slice ends before it starts: %s", path, row[req[start as uint]]));
+        }
+        res += [ {
+            origin: row[req[origin as uint]],
+            destination: row[req[destination as uint]],
+            start: s,
+            end: e,
+            trips: n
+        } ];
+    };
+    ret res;
+}
+
+/* individual requests for the flows between the given zones, in order of
+   departure. flows from or to zones without stops are skipped */
+fn generate_requests(zones: [ catchment ], flows: [ od_flow ], r: rng) -> [ request ] {
+    let by_id : map::hashmap<str, catchment> = map::str_hash();
+    for vec::each(zones) { |z|
+        by_id.insert(z.id, z);
+    }
+    let mut res = [];
+    for vec::each(flows) { |f|
+        let (from, to) = alt (by_id.find(f.origin), by_id.find(f.destination)) {
+            (some(a), some(b)) { (a, b) }
+            _ { cont; }
+        };
+        if vec::len(from.stop_ids) == 0u || vec::len(to.stop_ids) == 0u {
+            cont;
+        }
+        let n = poisson(r, f.trips);
+        let mut k = 0u;
+        while k < n {
+            let depart = f.start + (uniform(r) * ((f.end - f.start) as float)) as uint;
+            let origin = from.stop_ids[below(r, vec::len(from.stop_ids))];
+            let destination = to.stop_ids[below(r, vec::len(to.stop_ids))];
+            /* nothing to ride for */
+            if origin != destination {
+                res += [ { id: "", origin: origin, destination: destination, depart: depart } ];
+            }
+            k += 1u;
+        }
+    }
+    let sorted = sort::merge_sort({|a, b| a.depart <= b.depart }, res);
+    let mut i = 0u;
+    vec::map(sorted) { |q|
+        i += 1u;
+        { id: #fmt("agent-%u", i) with q }
+    }
+}
+
+/* in the form ridesim reads */
+fn requests_csv(requests: [ request ]) -> str {
+    let mut res = csv_row([ "agent_id", "origin_stop_id", "destination_stop_id", "departure_time" ]) + "\n";
+    for vec::each(requests) { |q|
+        res += csv_row([ q.id, q.origin, q.destination, time_format(q.depart) ]) + "\n";
+    }
+    ret res;
+}
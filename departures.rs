@@ -0,0 +1,59 @@
+
+use gtfs;
+import gtfs::gtfs_load;
+import gtfs::{feedaccess};
+
+fn usage() {
+    io::println("usage: departures <gtfs dir> <stop id> <YYYY-MM-DD> <HH:MM> [minutes]");
+}
+
+fn pickup_note(m: gtfs::marshal) -> str {
+    alt m {
+        gtfs::scheduled { "" }
+        gtfs::nopickup { "no pickup" }
+        gtfs::phoneahead { "phone agency to arrange" }
+        gtfs::coordinatewithdriver { "arrange with driver" }
+    }
+}
+
+fn main(args: [str])
+{
+    if vec::len(args) < 5u || vec::len(args) > 6u {
+        usage();
+        ret;
+    }
+    let stop_id = args[2];
+    let date = gtfs::parse_date(args[3]);
+    let after = alt vec::len(str::split_char(args[4], ':')) {
+        2u { gtfs::gettime(args[4] + ":00") }
+        _ { gtfs::gettime(args[4]) }
+    };
+    let window = if vec::len(args) == 6u {
+        alt uint::from_str(args[5]) {
+            some(m) { m * 60u }
+            none { fail("invalid number of minutes") }
+        }
+    } else {
+        3600u
+    };
+
+    let feed = gtfs_load(args[1]);
+    if !feed.stops.contains_key(stop_id) {
+        fail(#fmt("no such stop: %s", stop_id));
+    }
+    let stop = feed.stops.get(stop_id);
+    io::println(#fmt("departures from %s (%s) on %s from %s",
+            stop.name, stop.id, gtfs::date_format(date), gtfs::time_format(after)));
+    for vec::each(feed.departures(stop_id, date, after, window)) { |d|
+        let headsign = option::get_default(d.headsign, d.route.long_name);
+        let mut line = #fmt("%s  %-6s %-30s", gtfs::time_format(d.time), d.route.short_name, headsign);
+        if d.stop.id != stop_id {
+            line += #fmt(" [%s]", d.stop.name);
+        }
+        let note = pickup_note(d.pickup);
+        if note != "" {
+            line += #fmt(" (%s)", note);
+        }
+        io::println(line);
+    }
+}
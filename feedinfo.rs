@@ -1,11 +1,130 @@
 
 use gtfs;
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
 import gtfs::gtfs_load;
 import gtfs::{feedaccess};
+import gtfs::patterns::{find_patterns, direction_label};
+import gtfs::headway::{service_levels, levels_csv};
+
+fn usage() {
+    io::println("usage: feedinfo <gtfs dir>");
+    io::println("       feedinfo <gtfs dir> near <lat> <lon> <metres>");
+    io::println("       feedinfo <gtfs dir> nearest <lat> <lon> <count>");
+    io::println("       feedinfo <gtfs dir> within <south> <west> <north> <east>");
+    io::println("       feedinfo <gtfs dir> patterns [route id]");
+    io::println("       feedinfo <gtfs dir> headways <YYYY-MM-DD> [csv file]");
+}
+
+fn floatarg(s: str) -> float {
+    alt float::from_str(s) {
+        some(f) { f }
+        none { fail(#fmt("not a number: %s", s)) }
+    }
+}
+
+fn uintarg(s: str) -> uint {
+    alt uint::from_str(s) {
+        some(n) { n }
+        none { fail(#fmt("not a count: %s", s)) }
+    }
+}
+
+fn print_stop(stop: @gtfs::stop) {
+    io::println(#fmt("%s\t%s\t%f\t%f", stop.id, stop.name, stop.pt.lat, stop.pt.lon));
+}
+
+fn print_stops_by_distance(stops: [ (@gtfs::stop, float) ]) {
+    for vec::each(stops) { |sd|
+        let (stop, d) = sd;
+        io::println(#fmt("%s\t%s\t%f\t%f\t%.0fm", stop.id, stop.name, stop.pt.lat, stop.pt.lon, d));
+    }
+}
 
 fn main(args: [str])
 {
+    let nargs = vec::len(args);
+    if nargs < 2u {
+        usage();
+        ret;
+    }
     let feed = gtfs_load(args[1]);
-    io::println(feed.describe());
+    if nargs == 2u {
+        io::println(feed.describe());
+        ret;
+    }
+    alt args[2] {
+        "near" if nargs == 6u {
+            let pt = { lat: floatarg(args[3]), lon: floatarg(args[4]) };
+            print_stops_by_distance(feed.stops_within_radius(pt, floatarg(args[5])));
+        }
+        "nearest" if nargs == 6u {
+            let pt = { lat: floatarg(args[3]), lon: floatarg(args[4]) };
+            print_stops_by_distance(feed.nearest_stops(pt, uintarg(args[5])));
+        }
+        "within" if nargs == 7u {
+            let rect = {
+                sw: { lat: floatarg(args[3]), lon: floatarg(args[4]) },
+                ne: { lat: floatarg(args[5]), lon: floatarg(args[6]) }
+            };
+            for vec::each(feed.stops_in_rect(rect)) { |stop|
+                print_stop(stop);
+            }
+        }
+        "patterns" if nargs == 3u || nargs == 4u {
+            let mut trip_ids = [];
+            for feed.trips.each_value() { |trip|
+                if nargs == 3u || trip.route_id == args[3] {
+                    trip_ids += [ trip.id ];
+                }
+            }
+            for vec::each(find_patterns(feed, trip_ids)) { |p|
+                io::println(#fmt("%s\t%s\tdirection %s\t%u stops\t%u trips\trunning %s (%s-%s)",
+                        p.id, p.name, direction_label(p.direction),
+                        vec::len(p.stop_ids), vec::len(p.trip_ids),
+                        gtfs::time_format(p.running_time),
+                        gtfs::time_format(p.min_running_time),
+                        gtfs::time_format(p.max_running_time)));
+            }
+        }
+        "headways" if nargs == 4u || nargs == 5u {
+            let levels = service_levels(feed, gtfs::parse_date(args[3]));
+            if nargs == 5u {
+                gtfs::report::write_file(args[4], levels_csv(feed, levels));
+            }
+            /* summarise each route by its busiest stop */
+            let busiest : map::hashmap<str, gtfs::headway::service_level> = map::str_hash();
+            for vec::each(levels) { |l|
+                alt l.route_id {
+                    some(id) {
+                        alt busiest.find(id) {
+                            some(b) if b.departures >= l.departures {}
+                            _ { busiest.insert(id, l); }
+                        }
+                    }
+                    none {}
+                }
+            }
+            let mut route_ids = [];
+            for busiest.each_key() { |id| route_ids += [ id ]; }
+            for vec::each(std::sort::merge_sort({|a, b| a <= b }, route_ids)) { |id|
+                let l = busiest.get(id);
+                let route = feed.routes.get(id);
+                let fmt = { |t: option<uint>|
+                    alt t {
+                        some(s) { #fmt("%um", s / 60u) }
+                        none { "-" }
+                    }
+                };
+                io::println(#fmt("%s\t%s\t%u departures at %s\t%s-%s\tmean headway %s\tmax %s",
+                        route.short_name, route.long_name, l.departures,
+                        feed.stops.get(l.stop_id).name,
+                        gtfs::time_format(l.first), gtfs::time_format(l.last),
+                        fmt(l.mean_headway), fmt(l.max_headway)));
+            }
+        }
+        _ { usage(); }
+    }
 }
-
@@ -0,0 +1,409 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import std::sort;
+import gtfs::{feed, feedaccess, trip, route_type};
+import geom::{haversine};
+import report::{csv_row};
+
+export duty, blocks, trip_span, peak, peak_fleet, mode_peaks, peaks_csv, mode_name,
+       scheduling, default_scheduling, schedule_vehicles;
+
+/*
+ * vehicle duties: the trips one vehicle runs in a day, in order. between
+ * trips it lays over, and is still needed, so a duty holds its vehicle from
+ * the start of the first trip to the end of the last.
+ */
+
+type duty = {
+    /* the block_id, or for trips without one, the trip_id; scheduled
+       duties are numbered */
+    id: str,
+    trip_ids: [ str ]
+};
+
+/* the most vehicles needed at once, and when that first happens */
+type peak = {
+    key: str,
+    vehicles: uint,
+    at: uint
+};
+
+/* first departure and last arrival of a trip, or none if it has no stop
+   times loaded */
+fn trip_span(feed: feed, trip_id: str) -> option<(uint, uint)> {
+    alt feed.stop_times.find(trip_id) {
+        some(times) if vec::len(times) > 0u {
+            some((times[0].departure_time, times[vec::len(times) - 1u].arrival_time))
+        }
+        _ { none }
+    }
+}
+
+/* the span of a trip in a duty; blocks and schedule_vehicles only put
+   trips with stop times in duties */
+fn duty_span(feed: feed, trip_id: str) -> (uint, uint) {
+    alt trip_span(feed, trip_id) {
+        some(span) { span }
+        none { fail(#fmt("fleet: trip %s in a duty has no stop times", trip_id)) }
+    }
+}
+
+/* the given trips' duties according to their block_ids; a trip with no
+   block is a duty of its own, and trips without stop times are left out */
+fn blocks(feed: feed, trip_ids: [ str ]) -> [ duty ] {
+    let members : map::hashmap<str, [ (uint, str) ]> = map::str_hash();
+    let mut ids = [];
+    for vec::each(trip_ids) { |trip_id|
+        let start = alt trip_span(feed, trip_id) {
+            some((start, _)) { start }
+            none { cont; }
+        };
+        let trip = feed.trips.get(trip_id);
+        let id = alt trip.block_id {
+            some(b) if b != "" { b }
+            _ { trip_id }
+        };
+        let v = alt members.find(id) {
+            some(v) { v }
+            none { ids += [ id ]; [] }
+        };
+        members.insert(id, v + [ (start, trip_id) ]);
+    }
+    vec::map(ids) { |id|
+        let sorted = sort::merge_sort({|a, b|
+            let (sa, _) = a;
+            let (sb, _) = b;
+            sa <= sb
+        }, members.get(id));
+        { id: id, trip_ids: vec::map(sorted) { |st| let (_, t) = st; t } }
+    }
+}
+
+/* for each key, the peak number of the (key, start, end) intervals
+   overlapping at once. an interval ending when another starts doesn't
+   overlap it */
+fn peaks(intervals: [ (str, uint, uint) ]) -> [ peak ] {
+    let changes : map::hashmap<str, [ (uint, int) ]> = map::str_hash();
+    let mut keys = [];
+    for vec::each(intervals) { |iv|
+        let (key, start, end) = iv;
+        let v = alt changes.find(key) {
+            some(v) { v }
+            none { keys += [ key ]; [] }
+        };
+        changes.insert(key, v + [ (start, 1), (end, -1) ]);
+    }
+    let keys = sort::merge_sort({|a, b| a <= b }, keys);
+    vec::map(keys) { |key|
+        /* ends sort before starts at the same time */
+        let sorted = sort::merge_sort({|a, b|
+            let (ta, da) = a;
+            let (tb, db) = b;
+            ta < tb || (ta == tb && da <= db)
+        }, changes.get(key));
+        let mut count = 0;
+        let mut best = 0;
+        let mut at = 0u;
+        for vec::each(sorted) { |c|
+            let (t, d) = c;
+            count += d;
+            if count > best {
+                best = count;
+                at = t;
+            }
+        }
+        { key: key, vehicles: best as uint, at: at }
+    }
+}
+
+/* peak vehicles in service, by agency and by route. a vehicle belongs to
+   its duty's first agency all day, and to the route of the trip it's
+   running or has just run */
+fn peak_fleet(feed: feed, duties: [ duty ]) -> ([ peak ], [ peak ]) {
+    let mut by_agency = [];
+    let mut by_route = [];
+    for vec::each(duties) { |d|
+        let trips = vec::map(d.trip_ids) { |id| feed.trips.get(id) };
+        let spans = vec::map(d.trip_ids) { |id| duty_span(feed, id) };
+        let n = vec::len(trips);
+        let (first, _) = spans[0];
+        let (_, last) = spans[n - 1u];
+        let agency_id = feed.routes.get(trips[0].route_id).agency_id;
+        by_agency += [ (agency_id, first, last) ];
+        for uint::range(0u, n) { |i|
+            let (start, end) = spans[i];
+            let until = if i + 1u < n {
+                let (next, _) = spans[i + 1u];
+                uint::max(end, next)
+            } else {
+                end
+            };
+            by_route += [ (trips[i].route_id, start, until) ];
+        }
+    }
+    ret (peaks(by_agency), peaks(by_route));
+}
+
+fn mode_name(rt: route_type) -> str {
+    alt rt {
+        gtfs::tram { "tram" }
+        gtfs::subway { "subway" }
+        gtfs::rail { "rail" }
+        gtfs::bus { "bus" }
+        gtfs::ferry { "ferry" }
+        gtfs::cable_car { "cable_car" }
+        gtfs::gondola { "gondola" }
+        gtfs::funicular { "funicular" }
+    }
+}
+
+/* peak vehicles in service by mode, taking the mode of each duty's first
+   trip */
+fn mode_peaks(feed: feed, duties: [ duty ]) -> [ peak ] {
+    peaks(vec::map(duties) { |d|
+        let n = vec::len(d.trip_ids);
+        let (first, _) = duty_span(feed, d.trip_ids[0]);
+        let (_, last) = duty_span(feed, d.trip_ids[n - 1u]);
+        let route = feed.routes.get(feed.trips.get(d.trip_ids[0]).route_id);
+        (mode_name(route.route_type), first, last)
+    })
+}
+
+/* what a vehicle can do between trips */
+type scheduling = {
+    /* the least time at the terminal before the next trip */
+    min_layover: uint,
+    /* to run empty from one trip's last stop to another's first, in
+       straight-line metres per second; with none, a vehicle can only go
+       on from the stop it finished at */
+    deadhead_speed: option<float>,
+    /* the longest a vehicle will wait for its next trip; with none, as
+       long as it takes. every trip starting within it of a trip's end is a
+       candidate to follow it, so without a limit a whole day's trips
+       make a quadratic number of pairs */
+    max_wait: option<uint>
+};
+
+fn default_scheduling() -> scheduling {
+    { min_layover: 300u, deadhead_speed: some(5.), max_wait: some(14400u) }
+}
+
+/* hopcroft-karp, starting from the matching in next and prev. each phase
+   layers the trips by breadth-first search out from those without a
+   successor, then extends the matching along disjoint shortest augmenting
+   paths found by depth-first search through the layers. stops when a
+   phase finds no path */
+fn max_matching(succ: [ [ uint ] ], next: [ mut uint ], prev: [ mut uint ]) {
+    let n = vec::len(succ);
+    let never = uint::max_value;
+    loop {
+        /* layer of each trip, with unmatched trips at 0 */
+        let layer = vec::to_mut(vec::from_elem(n, never));
+        let mut queue = [];
+        for uint::range(0u, n) { |u|
+            if next[u] == never {
+                layer[u] = 0u;
+                queue += [ u ];
+            }
+        }
+        let mut found = false;
+        let mut head = 0u;
+        while head < vec::len(queue) {
+            let u = queue[head];
+            head += 1u;
+            for vec::each(succ[u]) { |v|
+                let w = prev[v];
+                if w == never {
+                    found = true;
+                } else if layer[w] == never {
+                    layer[w] = layer[u] + 1u;
+                    queue += [ w ];
+                }
+            }
+        }
+        if !found {
+            break;
+        }
+
+        /* the next successor each trip will try, and the path so far */
+        let tried = vec::to_mut(vec::from_elem(n, 0u));
+        let path = vec::to_mut(vec::from_elem(n, 0u));
+        for uint::range(0u, n) { |root|
+            if next[root] != never || layer[root] != 0u {
+                cont;
+            }
+            path[0] = root;
+            let mut depth = 1u;
+            while depth > 0u {
+                let u = path[depth - 1u];
+                if tried[u] == vec::len(succ[u]) {
+                    /* a dead end for the rest of this phase */
+                    layer[u] = never;
+                    depth -= 1u;
+                    cont;
+                }
+                let v = succ[u][tried[u]];
+                tried[u] += 1u;
+                let w = prev[v];
+                if w == never {
+                    /* each trip on the path takes the successor it last tried */
+                    let mut k = depth;
+                    while k > 0u {
+                        k -= 1u;
+                        let x = path[k];
+                        let y = succ[x][tried[x] - 1u];
+                        next[x] = y;
+                        prev[y] = x;
+                    }
+                    break;
+                } else if layer[w] == layer[u] + 1u {
+                    path[depth] = w;
+                    depth += 1u;
+                }
+            }
+        }
+    }
+}
+
+/* chains the given trips into as few duties as possible. vehicles stay
+   with an agency and a mode. each trip can be followed by any that starts
+   soon enough after it ends; the fewest duties is the number of trips
+   less the most such pairings, found by greedy matching then
+   hopcroft-karp */
+fn schedule_vehicles(feed: feed, trip_ids: [ str ], params: scheduling) -> [ duty ] {
+    let unsorted = vec::filter_map(trip_ids) { |id|
+        alt feed.stop_times.find(id) {
+            some(times) if vec::len(times) > 0u {
+                let n = vec::len(times);
+                let route = feed.routes.get(feed.trips.get(id).route_id);
+                some({
+                    id: id,
+                    start: times[0].departure_time,
+                    end: times[n - 1u].arrival_time,
+                    from: feed.stops.get(times[0].stop_id),
+                    to: feed.stops.get(times[n - 1u].stop_id),
+                    agency_id: route.agency_id,
+                    mode: mode_name(route.route_type)
+                })
+            }
+            _ { none }
+        }
+    };
+    let jobs = sort::merge_sort({|a, b| a.start <= b.start }, unsorted);
+    let n = vec::len(jobs);
+
+    /* who can follow whom */
+    let succ = vec::map(jobs) { |a|
+        let mut v = [];
+        /* the first job starting at or after a ends */
+        let mut lo = 0u;
+        let mut hi = n;
+        while lo < hi {
+            let mid = (lo + hi) / 2u;
+            if jobs[mid].start < a.end { lo = mid + 1u; } else { hi = mid; }
+        }
+        let mut j = lo;
+        while j < n {
+            alt params.max_wait {
+                some(w) if jobs[j].start > a.end + w { break; }
+                _ {}
+            }
+            let b = jobs[j];
+            if b.id != a.id && b.agency_id == a.agency_id && b.mode == a.mode {
+                let travel = if a.to.id == b.from.id {
+                    some(0u)
+                } else {
+                    alt params.deadhead_speed {
+                        some(speed) { some(float::ceil(haversine(a.to.pt, b.from.pt) / speed) as uint) }
+                        none { none }
+                    }
+                };
+                alt travel {
+                    some(secs) if a.end + params.min_layover + secs <= b.start { v += [ j ]; }
+                    _ {}
+                }
+            }
+            j += 1u;
+        }
+        v
+    };
+
+    let never = uint::max_value;
+    let mut next = vec::to_mut(vec::from_elem(n, never));
+    let mut prev = vec::to_mut(vec::from_elem(n, never));
+    /* greedily, each trip takes the earliest free successor */
+    for uint::range(0u, n) { |u|
+        for vec::each(succ[u]) { |v|
+            if prev[v] == never {
+                next[u] = v;
+                prev[v] = u;
+                break;
+            }
+        }
+    }
+    max_matching(succ, next, prev);
+
+    let mut res = [];
+    for uint::range(0u, n) { |u|
+        if prev[u] == never {
+            let mut chain = [ jobs[u].id ];
+            let mut v = u;
+            while next[v] != never {
+                v = next[v];
+                chain += [ jobs[v].id ];
+            }
+            res += [ { id: #fmt("vehicle-%u", vec::len(res) + 1u), trip_ids: chain } ];
+        }
+    }
+    ret res;
+}
+
+fn peaks_csv(kind: str, v: [ peak ]) -> str {
+    let mut res = csv_row([ kind, "vehicles", "at" ]) + "\n";
+    for vec::each(v) { |p|
+        res += csv_row([ p.key, uint::str(p.vehicles), gtfs::time_format(p.at) ]) + "\n";
+    }
+    ret res;
+}
+
+#[cfg(test)]
+mod tests {
+    import fleet::{schedule_vehicles};
+    import testfeed::{test_feed, add_stop, add_trip};
+
+    /* a and b both end at 07:00 or before, at z. p leaves z at 07:10, and q
+       leaves x, some 11 km away, at 07:20. a can run either, but b can
+       only get to p in time. greedily a takes p, the earlier, and b is
+       left without; the matching has to move a on to q */
+    #[test]
+    fn matching_reassigns_successors() {
+        let f = test_feed();
+        add_stop(f, "w", 0.5, 0.);
+        add_stop(f, "x", 0., 0.);
+        add_stop(f, "z", 0.1, 0.);
+        add_trip(f, "r", "a", [ ("w", 19800u, 19800u), ("z", 21600u, 21600u) ]);
+        add_trip(f, "r", "b", [ ("w", 23400u, 23400u), ("z", 25200u, 25200u) ]);
+        add_trip(f, "r", "p", [ ("z", 25800u, 25800u), ("w", 28800u, 28800u) ]);
+        add_trip(f, "r", "q", [ ("x", 26400u, 26400u), ("w", 28800u, 28800u) ]);
+        let duties = schedule_vehicles(f, [ "a", "b", "p", "q" ],
+                                       { min_layover: 300u, deadhead_speed: some(5.), max_wait: none });
+        assert(vec::len(duties) == 2u);
+        assert(duties[0].trip_ids == [ "a", "q" ]);
+        assert(duties[1].trip_ids == [ "b", "p" ]);
+    }
+
+    #[test]
+    fn waits_past_the_limit_need_another_vehicle() {
+        let f = test_feed();
+        add_stop(f, "x", 0., 0.);
+        add_stop(f, "y", 0., 0.01);
+        add_trip(f, "r", "early", [ ("x", 21600u, 21600u), ("y", 23400u, 23400u) ]);
+        add_trip(f, "r", "late", [ ("y", 36000u, 36000u), ("x", 37800u, 37800u) ]);
+        let ids = [ "early", "late" ];
+        let capped = { min_layover: 300u, deadhead_speed: none, max_wait: some(3600u) };
+        assert(vec::len(schedule_vehicles(f, ids, capped)) == 2u);
+        assert(vec::len(schedule_vehicles(f, ids, { max_wait: none with capped })) == 1u);
+    }
+}
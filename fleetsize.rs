@@ -0,0 +1,66 @@
+
+use gtfs;
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::gtfs_load;
+import gtfs::{feedaccess};
+import gtfs::fleet::{schedule_vehicles, default_scheduling, peak_fleet, mode_peaks, peaks_csv};
+
+fn usage() {
+    io::println("usage: fleetsize <gtfs dir> <YYYY-MM-DD> [min layover secs] [deadhead m/s, or 0 for none]");
+    io::println("       [max wait secs, or 0 for no limit]");
+}
+
+fn secsarg(s: str) -> uint {
+    alt uint::from_str(s) {
+        some(n) { n }
+        none { fail(#fmt("not a number of seconds: %s", s)) }
+    }
+}
+
+fn speedarg(s: str) -> float {
+    alt float::from_str(s) {
+        some(f) if f >= 0. { f }
+        _ { fail(#fmt("not a speed in m/s: %s", s)) }
+    }
+}
+
+fn main(args: [str])
+{
+    let nargs = vec::len(args);
+    if nargs < 3u || nargs > 6u {
+        usage();
+        ret;
+    }
+    let feed = gtfs_load(args[1]);
+    let date = gtfs::parse_date(args[2]);
+    let defaults = default_scheduling();
+    let params = {
+        min_layover: if nargs > 3u { secsarg(args[3]) } else { defaults.min_layover },
+        deadhead_speed: if nargs > 4u {
+            let s = speedarg(args[4]);
+            if s > 0. { some(s) } else { none }
+        } else {
+            defaults.deadhead_speed
+        },
+        max_wait: if nargs > 5u {
+            let w = secsarg(args[5]);
+            if w > 0u { some(w) } else { none }
+        } else {
+            defaults.max_wait
+        }
+    };
+
+    let service_ids = feed.active_service_ids(gtfs::weekday_of(date), date);
+    let trip_ids = vec::filter(feed.trip_ids_for_service_ids(service_ids)) { |id|
+        feed.stop_times.contains_key(id)
+    };
+    let duties = schedule_vehicles(feed, trip_ids, params);
+    io::println(#fmt("%u trips need %u vehicles", vec::len(trip_ids), vec::len(duties)));
+    let (by_agency, by_route) = peak_fleet(feed, duties);
+    io::print(peaks_csv("agency_id", by_agency));
+    io::print(peaks_csv("route_id", by_route));
+    io::print(peaks_csv("mode", mode_peaks(feed, duties)));
+}
@@ -0,0 +1,254 @@
+use std;
+import gtfs::{point, rectangle};
+
+export earth_radius, metres_per_degree, radians, degrees,
+       haversine, vincenty, bearing, destination,
+       rect_union, rect_intersection, rect_contains, rect_expand,
+       polyline_length, polyline_interpolate, point_in_ring, point_in_polygon,
+       projection, local_projection, project, unproject;
+
+/*
+ * distances are in metres and angles in degrees throughout. haversine is
+ * plenty for anything at city scale; vincenty is there for when it matters.
+ */
+
+/* mean radius of the earth, in metres */
+const earth_radius : float = 6371008.8;
+
+/* WGS84 ellipsoid */
+const wgs84_a : float = 6378137.;
+const wgs84_f : float = 0.0033528106647474805; /* 1/298.257223563 */
+
+fn radians(deg: float) -> float {
+    deg * float::consts::pi / 180.
+}
+
+fn degrees(rad: float) -> float {
+    rad * 180. / float::consts::pi
+}
+
+/* length of a degree of latitude, on a spherical earth */
+fn metres_per_degree() -> float {
+    radians(earth_radius)
+}
+
+/* great-circle distance in metres between two points, on a spherical earth */
+fn haversine(a: point, b: point) -> float {
+    let dlat = radians(b.lat - a.lat);
+    let dlon = radians(b.lon - a.lon);
+    let s1 = float::sin(dlat / 2.);
+    let s2 = float::sin(dlon / 2.);
+    let h = s1 * s1 + float::cos(radians(a.lat)) * float::cos(radians(b.lat)) * s2 * s2;
+    2. * earth_radius * float::asin(float::sqrt(float::fmin(1., h)))
+}
+
+/* distance in metres between two points on the WGS84 ellipsoid. the
+   iteration doesn't converge for nearly antipodal points, giving none */
+fn vincenty(a: point, b: point) -> option<float> {
+    let f = wgs84_f;
+    let b_axis = wgs84_a * (1. - f);
+    let l = radians(b.lon - a.lon);
+    let u1 = float::atan((1. - f) * float::tan(radians(a.lat)));
+    let u2 = float::atan((1. - f) * float::tan(radians(b.lat)));
+    let sin_u1 = float::sin(u1), cos_u1 = float::cos(u1);
+    let sin_u2 = float::sin(u2), cos_u2 = float::cos(u2);
+
+    let mut lambda = l;
+    let mut iterations = 0u;
+    loop {
+        let sin_lambda = float::sin(lambda), cos_lambda = float::cos(lambda);
+        let t1 = cos_u2 * sin_lambda;
+        let t2 = cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda;
+        let sin_sigma = float::sqrt(t1 * t1 + t2 * t2);
+        if sin_sigma == 0. {
+            /* coincident points */
+            ret some(0.);
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = float::atan2(sin_sigma, cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos_sq_alpha = 1. - sin_alpha * sin_alpha;
+        /* on the equator, cos_sq_alpha is zero */
+        let cos_2sigma_m = if cos_sq_alpha == 0. {
+            0.
+        } else {
+            cos_sigma - 2. * sin_u1 * sin_u2 / cos_sq_alpha
+        };
+        let c = f / 16. * cos_sq_alpha * (4. + f * (4. - 3. * cos_sq_alpha));
+        let prev = lambda;
+        lambda = l + (1. - c) * f * sin_alpha *
+            (sigma + c * sin_sigma * (cos_2sigma_m + c * cos_sigma * (-1. + 2. * cos_2sigma_m * cos_2sigma_m)));
+        iterations += 1u;
+        if float::abs(lambda - prev) < 1e-12 {
+            let u_sq = cos_sq_alpha * (wgs84_a * wgs84_a - b_axis * b_axis) / (b_axis * b_axis);
+            let ka = 1. + u_sq / 16384. * (4096. + u_sq * (-768. + u_sq * (320. - 175. * u_sq)));
+            let kb = u_sq / 1024. * (256. + u_sq * (-128. + u_sq * (74. - 47. * u_sq)));
+            let delta_sigma = kb * sin_sigma * (cos_2sigma_m + kb / 4. *
+                (cos_sigma * (-1. + 2. * cos_2sigma_m * cos_2sigma_m) -
+                 kb / 6. * cos_2sigma_m * (-3. + 4. * sin_sigma * sin_sigma) *
+                 (-3. + 4. * cos_2sigma_m * cos_2sigma_m)));
+            ret some(b_axis * ka * (sigma - delta_sigma));
+        }
+        if iterations >= 200u {
+            ret none;
+        }
+    }
+}
+
+/* initial bearing from a to b, clockwise from north, in [0, 360) */
+fn bearing(a: point, b: point) -> float {
+    let lat1 = radians(a.lat), lat2 = radians(b.lat);
+    let dlon = radians(b.lon - a.lon);
+    let y = float::sin(dlon) * float::cos(lat2);
+    let x = float::cos(lat1) * float::sin(lat2) - float::sin(lat1) * float::cos(lat2) * float::cos(dlon);
+    let deg = degrees(float::atan2(y, x));
+    if deg < 0. { deg + 360. } else { deg }
+}
+
+/* the point reached travelling `metres` from `start` on the given initial bearing */
+fn destination(start: point, bearing: float, metres: float) -> point {
+    let delta = metres / earth_radius;
+    let theta = radians(bearing);
+    let lat1 = radians(start.lat), lon1 = radians(start.lon);
+    let lat2 = float::asin(float::sin(lat1) * float::cos(delta) +
+                           float::cos(lat1) * float::sin(delta) * float::cos(theta));
+    let lon2 = lon1 + float::atan2(float::sin(theta) * float::sin(delta) * float::cos(lat1),
+                                   float::cos(delta) - float::sin(lat1) * float::sin(lat2));
+    let mut lon = degrees(lon2);
+    /* normalise to [-180, 180) */
+    while lon >= 180. { lon -= 360.; }
+    while lon < -180. { lon += 360.; }
+    { lat: degrees(lat2), lon: lon }
+}
+
+fn rect_union(a: rectangle, b: rectangle) -> rectangle {
+    {
+        sw: { lat: float::fmin(a.sw.lat, b.sw.lat), lon: float::fmin(a.sw.lon, b.sw.lon) },
+        ne: { lat: float::fmax(a.ne.lat, b.ne.lat), lon: float::fmax(a.ne.lon, b.ne.lon) }
+    }
+}
+
+fn rect_intersection(a: rectangle, b: rectangle) -> option<rectangle> {
+    let r = {
+        sw: { lat: float::fmax(a.sw.lat, b.sw.lat), lon: float::fmax(a.sw.lon, b.sw.lon) },
+        ne: { lat: float::fmin(a.ne.lat, b.ne.lat), lon: float::fmin(a.ne.lon, b.ne.lon) }
+    };
+    if r.sw.lat > r.ne.lat || r.sw.lon > r.ne.lon {
+        ret none;
+    }
+    ret some(r);
+}
+
+fn rect_contains(r: rectangle, pt: point) -> bool {
+    pt.lat >= r.sw.lat && pt.lat <= r.ne.lat &&
+        pt.lon >= r.sw.lon && pt.lon <= r.ne.lon
+}
+
+/* grows the rectangle by at least `metres` on every side */
+fn rect_expand(r: rectangle, metres: float) -> rectangle {
+    let dlat = metres / metres_per_degree();
+    /* a metre is most degrees of longitude at the edge furthest from the equator */
+    let lat = float::fmax(float::abs(r.sw.lat), float::abs(r.ne.lat));
+    let coslat = float::fmax(float::cos(radians(lat + dlat)), 0.01);
+    let dlon = dlat / coslat;
+    {
+        sw: { lat: float::fmax(r.sw.lat - dlat, -90.), lon: r.sw.lon - dlon },
+        ne: { lat: float::fmin(r.ne.lat + dlat, 90.), lon: r.ne.lon + dlon }
+    }
+}
+
+fn polyline_length(pts: [ point ]) -> float {
+    let mut total = 0.;
+    let mut i = 1u;
+    while i < vec::len(pts) {
+        total += haversine(pts[i - 1u], pts[i]);
+        i += 1u;
+    }
+    ret total;
+}
+
+/* the point `metres` along the polyline, clamped to its ends. each segment
+   is interpolated linearly in lat/lon, which is fine for short segments */
+fn polyline_interpolate(pts: [ point ], metres: float) -> point {
+    let n = vec::len(pts);
+    assert(n > 0u);
+    if metres <= 0. {
+        ret pts[0];
+    }
+    let mut travelled = 0.;
+    let mut i = 1u;
+    while i < n {
+        let seg = haversine(pts[i - 1u], pts[i]);
+        if seg > 0. && travelled + seg >= metres {
+            let t = (metres - travelled) / seg;
+            let a = pts[i - 1u], b = pts[i];
+            ret { lat: a.lat + (b.lat - a.lat) * t, lon: a.lon + (b.lon - a.lon) * t };
+        }
+        travelled += seg;
+        i += 1u;
+    }
+    ret pts[n - 1u];
+}
+
+/* by ray casting, treating lat/lon as planar; the ring may or may not
+   repeat its first point at the end */
+fn point_in_ring(pt: point, ring: [ point ]) -> bool {
+    let n = vec::len(ring);
+    if n == 0u {
+        ret false;
+    }
+    let mut inside = false;
+    let mut j = n - 1u;
+    let mut i = 0u;
+    while i < n {
+        let a = ring[i];
+        let b = ring[j];
+        if (a.lat > pt.lat) != (b.lat > pt.lat)
+            && pt.lon < (b.lon - a.lon) * (pt.lat - a.lat) / (b.lat - a.lat) + a.lon {
+            inside = !inside;
+        }
+        j = i;
+        i += 1u;
+    }
+    ret inside;
+}
+
+/* inside the first (outer) ring and none of the others (holes) */
+fn point_in_polygon(pt: point, rings: [ [ point ] ]) -> bool {
+    if vec::len(rings) == 0u || !point_in_ring(pt, rings[0]) {
+        ret false;
+    }
+    let mut i = 1u;
+    while i < vec::len(rings) {
+        if point_in_ring(pt, rings[i]) {
+            ret false;
+        }
+        i += 1u;
+    }
+    ret true;
+}
+
+/* an equirectangular projection onto a plane in metres, centred on
+   `origin`; x is east and y is north. good to a fraction of a percent over
+   the extent of a city, which is all we need for plotting */
+type projection = {
+    origin: point,
+    x_scale: float,
+    y_scale: float
+};
+
+fn local_projection(origin: point) -> projection {
+    {
+        origin: origin,
+        x_scale: metres_per_degree() * float::cos(radians(origin.lat)),
+        y_scale: metres_per_degree()
+    }
+}
+
+fn project(proj: projection, pt: point) -> (float, float) {
+    ((pt.lon - proj.origin.lon) * proj.x_scale, (pt.lat - proj.origin.lat) * proj.y_scale)
+}
+
+fn unproject(proj: projection, x: float, y: float) -> point {
+    { lat: proj.origin.lat + y / proj.y_scale, lon: proj.origin.lon + x / proj.x_scale }
+}
@@ -7,10 +7,15 @@ import std::sort;
 use csv;
 import csv::rowreader;
 import csv::{rowiter};
+import spatial::{stop_grid};
 
-export gtfs_load, feedaccess, feed, weekday, date,
-       agency, stop, route, trip, stop_time, calendar,
-       calendar_date;
+export gtfs_load, gtfs_load_parallel, gtfs_load_tables, gtfs_load_stop_times,
+       stop_times_each,
+       feedaccess, feed, weekday, date,
+       agency, stop, route, route_type, trip, shape, stop_time, calendar,
+       calendar_date, point, rectangle, point_format,
+       departure, marshal, marshal_allowed, direction, parse_date, gettime, time_format, date_format,
+       weekday_of, date_add_days, file_iter, default_workers;
 
 /* we want to build these higher-level concepts;
    [ Agency ]
@@ -30,6 +35,9 @@ type trips = map::hashmap<str, @trip>;
 type stop_times = map::hashmap<str, [ mut @stop_time ]>;
 type calendars = map::hashmap<str, @calendar>;
 type calendar_dates = map::hashmap<str, [ mut @calendar_date ]>;
+type shapes = map::hashmap<str, @shape>;
+/* built on first use, shared between copies of the feed */
+type stop_grid_cache = @{ mut grid: option<@stop_grid> };
 
 type feed = {
     agencies: agencies,
@@ -39,6 +47,8 @@ type feed = {
     stop_times: stop_times,
     calendars: calendars, 
     calendar_dates: calendar_dates,
+    shapes: shapes,
+    stop_grid_cache: stop_grid_cache,
 };
 
 type agency = {
@@ -118,6 +128,15 @@ type trip = {
     shape_id: option<str>
 };
 
+/* the path vehicles on a trip follow, with the distance travelled to each
+   point: in the feed's own units, as used by stop_time's
+   shape_dist_travelled, or in metres where the feed doesn't give them */
+type shape = {
+    id: str,
+    pts: [ point ],
+    dist_traveled: [ float ]
+};
+
 enum marshal {
     scheduled(),
     nopickup(),
@@ -125,6 +144,15 @@ enum marshal {
     coordinatewithdriver()
 }
 
+/* whether a pickup_type or drop_off_type lets anyone on or off: all but
+   none available do, if only by arrangement */
+fn marshal_allowed(m: option<marshal>) -> bool {
+    alt m {
+        some(nopickup) { false }
+        _ { true }
+    }
+}
+
 type stop_time = {
     trip_id: str,
     arrival_time: uint,
@@ -178,478 +206,970 @@ enum event {
     stop_departure(@trip, @stop_time)
 }
 
-fn gtfs_load(dir: str) -> feed
-{
-    fn file_iter(path: str, reqf: [(uint, str)], optf: [(uint, str)], f: fn(row: [str], req: [uint], opt: [option<uint>])) {
-        io::println("loading file: " + path);
-        let res = io::file_reader(path);
-        if result::is_failure(res) {
-            let err : str = result::get_err(res);
-            fail(#fmt("cannot open %s: %s", path, err));
-        }
-        let reader = csv::new_reader(result::get(res), ',', '"');
-        let mut row = [];
-        if !reader.readrow(row) {
-            fail(#fmt("%s: no column row", path));
-        }
-
-        let header = vec::map(row) { |t| str::trim(t) };
-
-        let mut req_lookup = [];
-        let mut i = 0u;
-        vec::iter(reqf) { |field|
-            let (enumval, fieldname) = field;
-            assert(enumval == i);
-            let pos = vec::position_elem(header, fieldname);
-            alt pos {
-                some(pos) { req_lookup += [pos] }
-                none { fail("required field not found in file") }
-            }
-            i += 1u;
-        };
-        let mut opt_lookup = [];
-        let mut i = 0u;
-        vec::iter(optf) { |field|
-            let (enumval, fieldname) = field;
-            assert(enumval == i);
-            opt_lookup += [vec::position_elem(header, fieldname)];
-            i += 1u;
-        };
-        for reader.iter() { |row| 
-            f(row, req_lookup, opt_lookup);
+/* a row on a stop's departure board */
+type departure = {
+    /* seconds after midnight on the day asked about; a trip from the
+       previous service day running past midnight appears here too */
+    time: uint,
+    stop: @stop,
+    route: @route,
+    trip: @trip,
+    stop_time: @stop_time,
+    headsign: option<str>,
+    pickup: marshal
+};
+
+fn file_iter(path: str, reqf: [(uint, str)], optf: [(uint, str)], f: fn(row: [str], req: [uint], opt: [option<uint>])) {
+    io::println("loading file: " + path);
+    let res = io::file_reader(path);
+    if result::is_failure(res) {
+        let err : str = result::get_err(res);
+        fail(#fmt("cannot open %s: %s", path, err));
+    }
+    let reader = csv::new_reader(result::get(res), ',', '"');
+    let mut row = [];
+    if !reader.readrow(row) {
+        fail(#fmt("%s: no column row", path));
+    }
+
+    let (req_lookup, opt_lookup) = field_lookup(path, row, reqf, optf);
+    for reader.iter() { |row| 
+        f(row, req_lookup, opt_lookup);
+    }
+}
+
+/* finds the column of each field in a file's header row */
+fn field_lookup(path: str, row: [str], reqf: [(uint, str)], optf: [(uint, str)]) -> ([uint], [option<uint>]) {
+    let header = vec::map(row) { |t| str::trim(t) };
+
+    let mut req_lookup = [];
+    let mut i = 0u;
+    vec::iter(reqf) { |field|
+        let (enumval, fieldname) = field;
+        assert(enumval == i);
+        let pos = vec::position_elem(header, fieldname);
+        alt pos {
+            some(pos) { req_lookup += [pos] }
+            none { fail("required field not found in file") }
         }
+        i += 1u;
     };
+    let mut opt_lookup = [];
+    let mut i = 0u;
+    vec::iter(optf) { |field|
+        let (enumval, fieldname) = field;
+        assert(enumval == i);
+        opt_lookup += [vec::position_elem(header, fieldname)];
+        i += 1u;
+    };
+    (req_lookup, opt_lookup)
+}
 
-    fn no_overwrite<T: copy, U: copy>(m: map::hashmap<T, U>, k: T, v: U) {
-        if ! m.insert(k, v) {
-            log(error, ("no_overwrite: duplicate key", k));
-            fail;
-        }
+fn getoption(row: [str], offset: option<uint>) -> option<str> {
+    alt offset {
+        some(n) { some(row[n]) }
+        none { none }
+    }
+}
+
+fn no_overwrite<T: copy, U: copy>(m: map::hashmap<T, U>, k: T, v: U) {
+    if ! m.insert(k, v) {
+        log(error, ("no_overwrite: duplicate key", k));
+        fail;
+    }
+}
+
+fn getdefault(row: [str], offset: option<uint>, default: str) -> str {
+    alt offset {
+        some(n) { row[n] }
+        none { default }
     }
+}
 
+fn floatfail(s: str) -> float {
+    alt float::from_str(s) {
+        some(n) { n }
+        none { fail("cannot convert str to floating point") }
+    }
+}
 
-    fn getdefault(row: [str], offset: option<uint>, default: str) -> str {
-        alt offset {
-            some(n) { row[n] }
-            none { default }
+fn getdate(s: str) -> date {
+    fn usub(s: str, offset: uint, len: uint) -> uint {
+        alt uint::from_str(str::substr(s, offset, len)) {
+            some(f) { f }
+            none { fail("invalid year code") }
         }
     }
+    let year = usub(s, 0u, 4u);
+    let month = usub(s, 4u, 2u);
+    let day = usub(s, 6u, 2u);
+    assert(month <= 12u);
+    assert(day <= 31u);
+    {
+        day: day,
+        month: month,
+        year: year,
+    }
+}
+
+/* accepts YYYY-MM-DD as well as GTFS's YYYYMMDD */
+fn parse_date(s: str) -> date {
+    let digits = str::replace(s, "-", "");
+    if str::len(digits) != 8u {
+        fail(#fmt("invalid date: %s", s));
+    }
+    getdate(digits)
+}
+
+fn date_format(d: date) -> str {
+    #fmt("%04u-%02u-%02u", d.year, d.month, d.day)
+}
+
+/* days since 1970-01-01 */
+fn date_ordinal(d: date) -> int {
+    let y = if d.month <= 2u { d.year as int - 1 } else { d.year as int };
+    let era = (if y >= 0 { y } else { y - 399 }) / 400;
+    let yoe = y - era * 400;
+    let m = d.month as int;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d.day as int - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn date_from_ordinal(n: int) -> date {
+    let z = n + 719468;
+    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (if month <= 2 { 1 } else { 0 });
+    {
+        day: day as uint,
+        month: month as uint,
+        year: year as uint
+    }
+}
 
-    fn getoption(row: [str], offset: option<uint>) -> option<str> {
-        alt offset {
-            some(n) { some(row[n]) }
+fn date_add_days(d: date, days: int) -> date {
+    date_from_ordinal(date_ordinal(d) + days)
+}
+
+fn weekday_of(d: date) -> weekday {
+    /* 1970-01-01 was a thursday */
+    let days = [ thursday, friday, saturday, sunday, monday, tuesday, wednesday ];
+    let n = date_ordinal(d) % 7;
+    days[(if n < 0 { n + 7 } else { n }) as uint]
+}
+
+fn load_agencies(fname: str) -> [ agency ] {
+    enum req { name, url, timezone }
+    let reqf = [
+        (name as uint, "agency_name"),
+        (url as uint, "agency_url"),
+        (timezone as uint, "agency_timezone")
+            ];
+    enum opt { id, lang, phone, fare_url }
+    let optf = [
+        (id as uint, "agency_id" ),
+        (lang as uint, "agency_lang"),
+        (phone as uint, "agency_phone"),
+        (fare_url as uint, "agency_fare_url")
+            ];
+    let mut res = [];
+    file_iter(fname, reqf, optf) { |row, req, opt|
+        let row_id = getdefault(row, opt[id as uint], "_");
+        res += [ {
+            id: row_id, 
+            name: row[req[name as uint]], 
+            url: row[req[url as uint]],
+            timezone: row[req[timezone as uint]],
+            lang: getoption(row, opt[lang as uint]),
+            phone: getoption(row, opt[phone as uint]),
+            fare_url: getoption(row, opt[fare_url as uint])
+        } ];
+    };
+    ret res;
+}
+
+fn load_stops(fname: str) -> [ stop ] {
+    fn get_location_type(loc: option<str>) -> option<location_type> {
+        alt loc{
+            some(s) {
+                if s == "" || s == "0" {
+                    some(location_stop)
+                } else if s == "1" {
+                    some(location_station)
+                } else {
+                    fail("impossible location")
+                }
+            }
             none { none }
         }
     }
+    enum req { id, name, lat, lon };
+    let reqf = [
+        (id as uint, "stop_id"),
+        (name as uint, "stop_name"),
+        (lat as uint, "stop_lat"),
+        (lon as uint, "stop_lon")
+            ];
+    enum opt { code, desc, zone_id, url, location_type, parent_station, timezone }
+    let optf = [
+        (code as uint, "stop_code"),
+        (desc as uint, "stop_desc"),
+        (zone_id as uint, "zone_id"),
+        (url as uint, "stop_url"),
+        (location_type as uint, "location_type"),
+        (parent_station as uint, "parent_station"),
+        (timezone as uint, "stop_timezone")
+            ];
+    let mut res = [];
+    file_iter(fname, reqf, optf) { |row, req, opt|
+        let stop_id = row[req[id as uint]];
+        res += [ {
+            id: stop_id, 
+            code: getoption(row, opt[code as uint]),
+            name : row[req[name as uint]],
+            pt : {
+                lat : floatfail(row[req[lat as uint]]), 
+                lon : floatfail(row[req[lon as uint]]), 
+            },
+            desc: getoption(row, opt[desc as uint]),
+            zone_id: getoption(row, opt[zone_id as uint]),
+            url: getoption(row, opt[url as uint]),
+            location_type: get_location_type(getoption(row, opt[location_type as uint])),
+            parent_station: getoption(row, opt[parent_station as uint]),
+            timezone: getoption(row, opt[timezone as uint])
+        } ];
+    };
+    ret res;
+}
 
-    fn floatfail(s: str) -> float {
-        alt float::from_str(s) {
-            some(n) { n }
-            none { fail("cannot convert str to floating point") }
+fn load_routes(fname: str) -> [ route ] {
+    fn get_route_type(rt: str) -> route_type {
+        alt rt {
+            "0" { tram }
+            "1" { subway }
+            "2" { rail }
+            "3" { bus }
+            "4" { ferry }
+            "5" { cable_car }
+            "6" { gondola }
+            "7" { funicular }
+            _ { fail("invalid route type") }
         }
     }
+    enum req { route_id, short_name, long_name, route_type };
+    let reqf = [
+        (route_id as uint, "route_id"),
+        (short_name as uint, "route_short_name"),
+        (long_name as uint, "route_long_name"),
+        (route_type as uint, "route_type")
+            ];
+    enum opt { agency_id, desc, url, color, text_color };
+    let optf = [
+        (agency_id as uint, "agency_id"),
+        (desc as uint, "route_desc"),
+        (url as uint, "route_url"),
+        (color as uint, "route_color"),
+        (text_color as uint, "route_text_color")
+            ];
+    let mut res = [];
+    file_iter(fname, reqf, optf) { |row, req, opt|
+        let id = row[req[route_id as uint]];
+        res += [ {
+            id: id,
+            agency_id: getdefault(row, opt[agency_id as uint], "_"),
+            short_name: row[req[short_name as uint]],
+            long_name: row[req[long_name as uint]],
+            desc: getoption(row, opt[desc as uint]),
+            route_type: get_route_type(row[req[route_type as uint]]),
+            url: getoption(row, opt[url as uint]),
+            color: getoption(row, opt[color as uint]),
+            text_color: getoption(row, opt[text_color as uint])
+        } ];
+    };
+    ret res;
+}
 
-    fn getdate(s: str) -> date {
-        fn usub(s: str, offset: uint, len: uint) -> uint {
-            alt uint::from_str(str::substr(s, offset, len)) {
-                some(f) { f }
-                none { fail("invalid year code") }
+fn load_trips(fname: str) -> [ trip ] {
+    fn getdirection(d: option<str>) -> option<direction> {
+        alt d {
+            some(s) {
+                alt(s) {
+                    "0" { some(oneway) }
+                    "1" { some(theotherway) }
+                    _   { fail("invalid direction_id") }
+                }
             }
+            none { none }
         }
-        let year = usub(s, 0u, 4u);
-        let month = usub(s, 4u, 2u);
-        let day = usub(s, 6u, 2u);
-        assert(month <= 12u);
-        assert(day <= 31u);
-        {
-            day: day,
-            month: month,
-            year: year,
-        }
-    }
-
-    fn load_agencies(fname: str, agencies: agencies) {
-        enum req { name, url, timezone }
-        let reqf = [
-            (name as uint, "agency_name"),
-            (url as uint, "agency_url"),
-            (timezone as uint, "agency_timezone")
-                ];
-        enum opt { id, lang, phone, fare_url }
-        let optf = [
-            (id as uint, "agency_id" ),
-            (lang as uint, "agency_lang"),
-            (phone as uint, "agency_phone"),
-            (fare_url as uint, "agency_fare_url")
-                ];
-        file_iter(fname, reqf, optf) { |row, req, opt|
-            let row_id = getdefault(row, opt[id as uint], "_");
-            no_overwrite(agencies, row_id, @{
-                id: row_id, 
-                name: row[req[name as uint]], 
-                url: row[req[url as uint]],
-                timezone: row[req[timezone as uint]],
-                lang: getoption(row, opt[lang as uint]),
-                phone: getoption(row, opt[phone as uint]),
-                fare_url: getoption(row, opt[fare_url as uint])
-            });
+    }
+    enum req { route_id, service_id, trip_id };
+    let reqf = [
+        (route_id as uint, "route_id"),
+        (service_id as uint, "service_id"),
+        (trip_id as uint, "trip_id")
+            ];
+    enum opt { headsign, short_name, direction_id, block_id, shape_id }
+    let optf = [
+        (headsign as uint, "trip_headsign"),
+        (short_name as uint, "trip_short_name"),
+        (direction_id as uint, "direction_id"),
+        (block_id as uint, "block_id"),
+        (shape_id as uint, "shape_id")
+            ];
+    let mut res = [];
+    file_iter(fname, reqf, optf) { |row, req, opt|
+        let id = row[req[trip_id as uint]];
+        res += [ {
+            id: id, 
+            route_id: row[req[route_id as uint]],
+            service_id: row[req[service_id as uint]],
+            headsign: getoption(row, opt[headsign as uint]),
+            short_name: getoption(row, opt[short_name as uint]),
+            direction: getdirection(getoption(row, opt[direction_id as uint])),
+            block_id: getoption(row, opt[block_id as uint]),
+            shape_id: getoption(row, opt[shape_id as uint]),
+        } ];
+    };
+    ret res;
+}
+
+fn load_calendars(fname: str) -> [ calendar ] {
+    fn getbool(s: str) -> bool {
+        alt(s) {
+            "0" { false }
+            "1" { true }
+            _   { fail("invalid boolean value") }
+        }
+    }
+    enum req { id, mon, tue, wed, thu, fri, sat, sun, start, end };
+    let reqf = [
+        (id as uint, "service_id"),
+        (mon as uint, "monday"),
+        (tue as uint, "tuesday"),
+        (wed as uint, "wednesday"),
+        (thu as uint, "thursday"),
+        (fri as uint, "friday"),
+        (sat as uint, "saturday"),
+        (sun as uint, "sunday"),
+        (start as uint, "start_date"),
+        (end as uint, "end_date")
+            ];
+    let day_enum = [
+        (monday, mon as uint),
+        (tuesday, tue as uint),
+        (wednesday, wed as uint),
+        (thursday, thu as uint),
+        (friday, fri as uint),
+        (saturday, sat as uint),
+        (sunday, sun as uint)
+            ];
+    let mut res = [];
+    file_iter(fname, reqf, []) { |row,req,opt|
+        let service_id = row[req[id as uint]];
+        let mut weekdays = [];
+        for vec::each(day_enum) { |tp|
+            let (day, en) = tp;
+            if getbool(row[req[en]]) {
+                weekdays += [ day ];
+            }
         };
+        res += [ {
+            service_id: service_id,
+            weekdays: weekdays,
+            start_date: getdate(row[req[start as uint]]),
+            end_date: getdate(row[req[end as uint]])
+        } ];
     };
+    ret res;
+}
+
+fn load_calendar_dates(fname: str) -> [ calendar_date ] {
+    fn get_exception(s: str) -> exception {
+        alt s {
+            "1" { service_added }
+            "2" { service_removed }
+            _ { fail }
+        }
+    }
+    enum req { id, date, exception_type }
+    let req = [
+        (id as uint, "service_id"),
+        (date as uint, "date"),
+        (exception_type as uint, "exception_type")
+            ];
+    let mut res = [];
+    file_iter(fname, req, []) { |row,req,opt|
+        res += [ {
+            service_id: row[req[id as uint]],
+            date: getdate(row[req[date as uint]]),
+            exception_type: get_exception(row[req[exception_type as uint]])
+        } ];
+    };
+    ret res;
+}
 
-    fn load_stops(fname: str, stops: stops) {
-        fn get_location_type(loc: option<str>) -> option<location_type> {
-            alt loc{
-                some(s) {
-                    if s == "" || s == "0" {
-                        some(location_stop)
-                    } else if s == "1" {
-                        some(location_station)
+/* shapes.txt is optional; without it there are no shapes */
+fn load_shapes(fname: str) -> [ shape ] {
+    if !os::path_exists(fname) {
+        ret [];
+    }
+    enum req { id, lat, lon, sequence }
+    enum opt { dist_traveled }
+    let reqf = [
+        (id as uint, "shape_id"),
+        (lat as uint, "shape_pt_lat"),
+        (lon as uint, "shape_pt_lon"),
+        (sequence as uint, "shape_pt_sequence")
+            ];
+    let optf = [
+        (dist_traveled as uint, "shape_dist_traveled")
+            ];
+    type shape_pt = { seq: uint, pt: point, dist: option<float> };
+    let pts : map::hashmap<str, [ mut shape_pt ]> = map::str_hash();
+    let mut ids = [];
+    file_iter(fname, reqf, optf) { |row, req, opt|
+        let id = row[req[id as uint]];
+        let seq = alt uint::from_str(row[req[sequence as uint]]) {
+            some(n) { n }
+            none { fail(#fmt("%s: bad shape_pt_sequence", fname)) }
+        };
+        let dist = alt getoption(row, opt[dist_traveled as uint]) {
+            some(d) if d != "" { some(floatfail(d)) }
+            _ { none }
+        };
+        let mut v = alt pts.find(id) {
+            some(v) { v }
+            none { ids += [ id ]; [mut] }
+        };
+        v += [ {
+            seq: seq,
+            pt: { lat: floatfail(row[req[lat as uint]]), lon: floatfail(row[req[lon as uint]]) },
+            dist: dist
+        } ];
+        pts.insert(id, v);
+    };
+    vec::map(ids) { |id|
+        let v = pts.get(id);
+        sort::quick_sort3({|a, b| a.seq < b.seq }, {|a, b| a.seq == b.seq }, v);
+        let given = vec::all(v) { |p| option::is_some(p.dist) };
+        let mut dists = [];
+        let mut travelled = 0.;
+        for vec::eachi(v) { |i, p|
+            if given {
+                dists += [ option::get(p.dist) ];
+            } else {
+                if i > 0u {
+                    travelled += geom::haversine(v[i - 1u].pt, p.pt);
+                }
+                dists += [ travelled ];
+            }
+        }
+        { id: id, pts: vec::map(v) { |p| p.pt }, dist_traveled: dists }
+    }
+}
+
+fn gettime(s: str) -> uint {
+    if s == "" {
+        fail("missing times are currently unsupported")
+    } else {
+        let tc : [str] = str::split_char(s, ':');
+        if vec::len(tc) != 3u {
+            fail("incorrect time component length");
+        }
+        let lens = vec::map(tc, {|t| str::len(t)});
+        assert(lens[0] == 1u || lens[0] == 2u);
+        assert(lens[1] == 2u);
+        assert(lens[2] == 2u);
+        let mut secs = 0u;
+        alt uint::from_str(tc[0]) {
+            some(v) { secs += v * 3600u; }
+            _ { fail("invalid hour"); }
+        }
+        fn minsec(s: str) -> uint {
+            alt uint::from_str(s) {
+                some(v) {
+                    if v > 59u {
+                        fail("invalid minute");
                     } else {
-                        fail("impossible location")
+                        v
                     }
                 }
-                none { none }
+                _ { fail("invalid minute"); }
             }
         }
-        enum req { id, name, lat, lon };
-        let reqf = [
-            (id as uint, "stop_id"),
-            (name as uint, "stop_name"),
-            (lat as uint, "stop_lat"),
-            (lon as uint, "stop_lon")
-                ];
-        enum opt { code, desc, zone_id, url, location_type, parent_station, timezone }
-        let optf = [
-            (code as uint, "stop_code"),
-            (desc as uint, "stop_desc"),
-            (zone_id as uint, "zone_id"),
-            (url as uint, "stop_url"),
-            (location_type as uint, "location_type"),
-            (parent_station as uint, "parent_station"),
-            (timezone as uint, "stop_timezone")
-                ];
-        file_iter(fname, reqf, optf) { |row, req, opt|
-            let stop_id = row[req[id as uint]];
-            no_overwrite(stops, stop_id, @{
-                id: stop_id, 
-                code: getoption(row, opt[code as uint]),
-                name : row[req[name as uint]],
-                pt : {
-                    lat : floatfail(row[req[lat as uint]]), 
-                    lon : floatfail(row[req[lon as uint]]), 
-                },
-                desc: getoption(row, opt[desc as uint]),
-                zone_id: getoption(row, opt[zone_id as uint]),
-                url: getoption(row, opt[url as uint]),
-                location_type: get_location_type(getoption(row, opt[location_type as uint])),
-                parent_station: getoption(row, opt[parent_station as uint]),
-                timezone: getoption(row, opt[timezone as uint])
-            });
-        };
-    };
+        secs += minsec(tc[1]) * 60u;
+        secs += minsec(tc[2]);
+        secs
+    }
+}
 
-    fn load_routes(fname: str, routes: routes) {
-        fn get_route_type(rt: str) -> route_type {
-            alt rt {
-                "0" { tram }
-                "1" { subway }
-                "2" { rail }
-                "3" { bus }
-                "4" { ferry }
-                "5" { cable_car }
-                "6" { gondola }
-                "7" { funicular }
-                _ { fail("invalid route type") }
+fn getmarshal(s: option<str>) -> option<marshal> {
+    alt s {
+        some(v) {
+            alt v {
+                "0" { some(scheduled) }
+                "1" { some(nopickup) }
+                "2" { some(phoneahead) }
+                "3" { some(coordinatewithdriver) }
+                _ { fail("unknown marshal type") }
             }
         }
-        enum req { route_id, short_name, long_name, route_type };
-        let reqf = [
-            (route_id as uint, "route_id"),
-            (short_name as uint, "route_short_name"),
-            (long_name as uint, "route_long_name"),
-            (route_type as uint, "route_type")
-                ];
-        enum opt { agency_id, desc, url, color, text_color };
-        let optf = [
-            (agency_id as uint, "agency_id"),
-            (desc as uint, "route_desc"),
-            (url as uint, "route_url"),
-            (color as uint, "route_color"),
-            (text_color as uint, "route_text_color")
-                ];
-        file_iter(fname, reqf, optf) { |row, req, opt|
-            let id = row[req[route_id as uint]];
-            no_overwrite(routes, id, @{
-                id: id,
-                agency_id: getdefault(row, opt[agency_id as uint], "_"),
-                short_name: row[req[short_name as uint]],
-                long_name: row[req[long_name as uint]],
-                desc: getoption(row, opt[desc as uint]),
-                route_type: get_route_type(row[req[route_type as uint]]),
-                url: getoption(row, opt[url as uint]),
-                color: getoption(row, opt[color as uint]),
-                text_color: getoption(row, opt[text_color as uint])
-            });
-        };
+        none { none }
     }
+}
 
-    fn load_trips(fname: str, trips: trips) {
-        fn getdirection(d: option<str>) -> option<direction> {
-            alt d {
-                some(s) {
-                    alt(s) {
-                        "0" { some(oneway) }
-                        "1" { some(theotherway) }
-                        _   { fail("invalid direction_id") }
-                    }
+/* stop_times.txt is parsed both here and in the worker tasks of
+   gtfs_load_parallel, so the field lists live at module level */
+enum st_req { st_trip_id, st_arrival_time, st_departure_time, st_stop_id, st_stop_sequence }
+/* st_travelled is the spelling some old feeds used */
+enum st_opt { st_headsign, st_pickup_type, st_drop_off_type, st_traveled, st_travelled }
+
+fn stop_time_fields() -> ([(uint, str)], [(uint, str)]) {
+    ([
+        (st_trip_id as uint, "trip_id"),
+        (st_arrival_time as uint, "arrival_time"),
+        (st_departure_time as uint, "departure_time"),
+        (st_stop_id as uint, "stop_id"),
+        (st_stop_sequence as uint, "stop_sequence")
+            ], [
+        (st_headsign as uint, "stop_headsign"),
+        (st_pickup_type as uint, "pickup_type"),
+        (st_drop_off_type as uint, "drop_off_type"),
+        (st_traveled as uint, "shape_dist_traveled"),
+        (st_travelled as uint, "shape_dist_travelled")
+            ])
+}
+
+fn shape_dist(s: str) -> option<float> {
+    alt float::from_str(s) {
+        some(f) { some(f) }
+        none { fail("invalid shape_dist_traveled") }
+    }
+}
+
+fn parse_stop_time(row: [str], req: [uint], opt: [option<uint>]) -> stop_time {
+    let seq = alt uint::from_str(row[req[st_stop_sequence as uint]]) {
+        some(v) { v }
+        _ { fail("invalid stop_sequence") }
+    };
+    {
+        trip_id: row[req[st_trip_id as uint]],
+        arrival_time: gettime(row[req[st_arrival_time as uint]]),
+        departure_time: gettime(row[req[st_departure_time as uint]]),
+        stop_id: row[req[st_stop_id as uint]],
+        sequence: seq,
+        headsign: getoption(row, opt[st_headsign as uint]),
+        pickup_type: getmarshal(getoption(row, opt[st_pickup_type as uint])),
+        drop_off_type: getmarshal(getoption(row, opt[st_drop_off_type as uint])),
+        shape_dist_travelled: alt getoption(row, opt[st_traveled as uint]) {
+            some(s) if s != "" { shape_dist(s) }
+            _ {
+                alt getoption(row, opt[st_travelled as uint]) {
+                    some(s) if s != "" { shape_dist(s) }
+                    _ { none }
                 }
-                none { none }
             }
         }
-        enum req { route_id, service_id, trip_id };
-        let reqf = [
-            (route_id as uint, "route_id"),
-            (service_id as uint, "service_id"),
-            (trip_id as uint, "trip_id")
-                ];
-        enum opt { headsign, short_name, direction_id, block_id, shape_id }
-        let optf = [
-            (headsign as uint, "trip_headsign"),
-            (short_name as uint, "trip_short_name"),
-            (direction_id as uint, "direction_id"),
-            (block_id as uint, "block_id"),
-            (shape_id as uint, "shape_id")
-                ];
-        file_iter(fname, reqf, optf) { |row, req, opt|
-            let id = row[req[trip_id as uint]];
-            no_overwrite(trips, id, @{
-                id: id, 
-                route_id: row[req[route_id as uint]],
-                service_id: row[req[service_id as uint]],
-                headsign: getoption(row, opt[headsign as uint]),
-                short_name: getoption(row, opt[short_name as uint]),
-                direction: getdirection(getoption(row, opt[direction_id as uint])),
-                block_id: getoption(row, opt[block_id as uint]),
-                shape_id: getoption(row, opt[shape_id as uint]),
-            });
-        };
     }
-    fn load_stop_times(fname: str, stop_times: stop_times) {
-        fn gettime(s: str) -> uint {
-            if s == "" {
-                fail("missing times are currently unsupported")
-            } else {
-                let tc : [str] = str::split_char(s, ':');
-                if vec::len(tc) != 3u {
-                    fail("incorrect time component length");
-                }
-                let lens = vec::map(tc, {|t| str::len(t)});
-                assert(lens[0] == 1u || lens[0] == 2u);
-                assert(lens[1] == 2u);
-                assert(lens[2] == 2u);
-                let mut secs = 0u;
-                alt uint::from_str(tc[0]) {
-                    some(v) { secs += v * 3600u; }
-                    _ { fail("invalid hour"); }
-                }
-                fn minsec(s: str) -> uint {
-                    alt uint::from_str(s) {
-                        some(v) {
-                            if v > 59u {
-                                fail("invalid minute");
-                            } else {
-                                v
+}
+
+/* hands each trip's stop times to `f` as a run, sorted by sequence. rows for
+   a trip are normally contiguous in stop_times.txt, so we only hold the run
+   currently being read; if the file isn't grouped by trip, `f` will see the
+   same trip_id more than once. */
+fn load_stop_times(fname: str, f: fn(trip_id: str, times: [ mut @stop_time ])) {
+    let (reqf, optf) = stop_time_fields();
+    let mut run_id = "";
+    let mut run : [ mut @stop_time ] = [mut];
+    file_iter(fname, reqf, optf) { |row,req,opt|
+        let time = @parse_stop_time(row, req, opt);
+        if time.trip_id != run_id {
+            if vec::len(run) > 0u {
+                sort_by_sequence(run);
+                f(run_id, run);
+            }
+            run_id = time.trip_id;
+            run = [mut];
+            vec::reserve(run, 16u);
+        }
+        run += [ time ];
+    };
+    if vec::len(run) > 0u {
+        sort_by_sequence(run);
+        f(run_id, run);
+    }
+}
+
+fn sort_by_sequence(times: [ mut @stop_time ]) {
+    sort::quick_sort3({|v1,v2| v1.sequence < v2.sequence},
+            {|v1,v2| v1.sequence == v2.sequence}, times);
+}
+
+/* runs for trips already seen, when the input isn't grouped by trip; they
+   are merged into the trip's stop times once everything has been read */
+type late_runs = map::hashmap<str, @{ mut runs: [ [ mut @stop_time ] ] }>;
+
+fn add_stop_times(stop_times: stop_times, late: late_runs, trip_id: str, times: [ mut @stop_time ]) {
+    if stop_times.contains_key(trip_id) {
+        /* we can't assume stop times are grouped by trip in the
+           input file; this is the slow path */
+        alt late.find(trip_id) {
+            some(l) { l.runs += [ times ]; }
+            none { late.insert(trip_id, @{ mut runs: [ times ] }); }
+        }
+    } else {
+        stop_times.insert(trip_id, times);
+    }
+}
+
+/* joins the late runs onto their trips, sorting each trip just once */
+fn merge_late_runs(stop_times: stop_times, late: late_runs) {
+    for late.each() { |trip_id, l|
+        let mut merged = stop_times.get(trip_id);
+        for vec::each(l.runs) { |run| merged += run; }
+        sort_by_sequence(merged);
+        stop_times.insert(trip_id, merged);
+    }
+}
+
+/* results sent back to the loading task by the worker tasks */
+enum loaded {
+    loaded_agencies([ agency ]),
+    loaded_stops([ stop ]),
+    loaded_routes([ route ]),
+    loaded_trips([ trip ]),
+    loaded_calendars([ calendar ]),
+    loaded_calendar_dates([ calendar_date ]),
+    loaded_shapes([ shape ]),
+    loaded_stop_times(uint, [ stop_time ]),
+    loaded_chunk_count(uint),
+    /* a task failed loading what's named; the loader fails in turn rather
+       than waiting forever for it */
+    loaded_failed(str)
+}
+
+const table_count : uint = 7u;
+
+/* rows of stop_times.txt handed to a worker task at a time */
+const stop_time_chunk : uint = 50000u;
+
+/* runs `load` in a task of its own, sending back what it loaded, or
+   loaded_failed(what) if it fails */
+fn spawn_loader(out: comm::chan<loaded>, what: str, load: fn~() -> loaded) {
+    task::spawn { ||
+        alt task::try(load) {
+            result::ok(msg) { comm::send(out, msg); }
+            result::err(_) { comm::send(out, loaded_failed(what)); }
+        }
+    };
+}
+
+fn spawn_table_loaders(dir: str, out: comm::chan<loaded>) {
+    spawn_loader(out, "agency.txt") { ||
+        loaded_agencies(load_agencies(path::connect(dir, "agency.txt")))
+    };
+    spawn_loader(out, "stops.txt") { ||
+        loaded_stops(load_stops(path::connect(dir, "stops.txt")))
+    };
+    spawn_loader(out, "routes.txt") { ||
+        loaded_routes(load_routes(path::connect(dir, "routes.txt")))
+    };
+    spawn_loader(out, "trips.txt") { ||
+        loaded_trips(load_trips(path::connect(dir, "trips.txt")))
+    };
+    spawn_loader(out, "calendar.txt") { ||
+        loaded_calendars(load_calendars(path::connect(dir, "calendar.txt")))
+    };
+    spawn_loader(out, "calendar_dates.txt") { ||
+        loaded_calendar_dates(load_calendar_dates(path::connect(dir, "calendar_dates.txt")))
+    };
+    spawn_loader(out, "shapes.txt") { ||
+        loaded_shapes(load_shapes(path::connect(dir, "shapes.txt")))
+    };
+}
+
+/* reads stop_times.txt a line at a time, and hands out numbered chunks of
+   lines to `nworkers` parsing tasks. each chunk comes back on `out` as a
+   loaded_stop_times; the number of chunks follows once the file is read.
+   at most `in_flight` chunks are out at once: the reader waits for a ()
+   on the returned channel, sent as each chunk is merged, before handing
+   out more. splitting on lines means a quoted field can't contain a
+   newline, which we've never seen in a stop_times.txt */
+fn spawn_stop_time_loaders(fname: str, nworkers: uint, in_flight: uint,
+                           out: comm::chan<loaded>) -> comm::chan<()> {
+    enum chunk {
+        chunk_lines(uint, [ str ]),
+        no_more_chunks
+    }
+    task::spawn_listener { |merged: comm::port<()>|
+        io::println("loading file: " + fname);
+        let res = io::file_reader(fname);
+        if result::is_failure(res) {
+            comm::send(out, loaded_failed(fname));
+            ret;
+        }
+        let rdr = result::get(res);
+        let mut header = [];
+        if !csv::new_reader(io::str_reader(rdr.read_line()), ',', '"').readrow(header) {
+            comm::send(out, loaded_failed(fname));
+            ret;
+        }
+        let (req, opt) = alt task::try { ||
+            let (reqf, optf) = stop_time_fields();
+            field_lookup(fname, header, reqf, optf)
+        } {
+            result::ok(lookup) { lookup }
+            result::err(_) {
+                comm::send(out, loaded_failed(fname));
+                ret;
+            }
+        };
+
+        let workers = vec::from_fn(uint::max(nworkers, 1u)) { |_i|
+            task::spawn_listener { |po: comm::port<chunk>|
+                loop {
+                    alt comm::recv(po) {
+                        chunk_lines(n, lines) {
+                            let parsed = task::try { ||
+                                let text = str::connect(lines, "\n");
+                                let reader = csv::new_reader(io::str_reader(text), ',', '"');
+                                let mut times = [];
+                                vec::reserve(times, vec::len(lines));
+                                for reader.iter() { |row|
+                                    times += [ parse_stop_time(row, req, opt) ];
+                                }
+                                times
+                            };
+                            alt parsed {
+                                result::ok(times) { comm::send(out, loaded_stop_times(n, times)); }
+                                result::err(_) { comm::send(out, loaded_failed(fname)); }
                             }
                         }
-                        _ { fail("invalid minute"); }
+                        no_more_chunks { break; }
                     }
                 }
-                secs += minsec(tc[1]) * 60u;
-                secs += minsec(tc[2]);
-                secs
             }
-        }
-        fn getmarshal(s: option<str>) -> option<marshal> {
-            alt s {
-                some(v) {
-                    alt v {
-                        "0" { some(scheduled) }
-                        "1" { some(nopickup) }
-                        "2" { some(phoneahead) }
-                        "3" { some(coordinatewithdriver) }
-                        _ { fail("unknown marshal type") }
-                    }
+        };
+
+        let limit = uint::max(in_flight, 1u);
+        let mut out_now = 0u;
+        let mut n = 0u;
+        let mut lines = [];
+        vec::reserve(lines, stop_time_chunk);
+        loop {
+            let eof = rdr.eof();
+            if !eof {
+                let line = rdr.read_line();
+                if line != "" {
+                    lines += [ line ];
                 }
-                none { none }
             }
-        }
-        enum req { trip_id, arrival_time, departure_time, stop_id, stop_sequence };
-        let reqf = [
-            (trip_id as uint, "trip_id"),
-            (arrival_time as uint, "arrival_time"),
-            (departure_time as uint, "departure_time"),
-            (stop_id as uint, "stop_id"),
-            (stop_sequence as uint, "stop_sequence")
-                ];
-        enum opt { headsign, pickup_type, drop_off_type, travelled };
-        let optf = [
-            (headsign as uint, "stop_headsign"),
-            (pickup_type as uint, "pickup_type"),
-            (drop_off_type as uint, "drop_off_type"),
-            (travelled as uint, "shape_dist_travelled")
-                ];
-
-        file_iter(fname, reqf, optf) { |row,req,opt|
-            let seq = alt uint::from_str(row[req[stop_sequence as uint]]) {
-                some(v) { v }
-                _ { fail("invalid stop_sequence") }
-            };
-            let id = row[req[trip_id as uint]];
-            let mut trip_list = if stop_times.contains_key(id) {
-                stop_times.get(id)
-            } else {
-                let mut n = [mut];
-                vec::reserve(n, 16u);
-                n
-            };
-            let time =  @ {
-                trip_id: id, 
-                arrival_time: gettime(row[req[arrival_time as uint]]),
-                departure_time: gettime(row[req[departure_time as uint]]),
-                stop_id: row[req[stop_id as uint]],
-                sequence: seq,
-                headsign: getoption(row, opt[headsign as uint]),
-                pickup_type: getmarshal(getoption(row, opt[pickup_type as uint])),
-                drop_off_type: getmarshal(getoption(row, opt[drop_off_type as uint])),
-                shape_dist_travelled: alt getoption(row, opt[travelled as uint]) {
-                    some(s) {
-                        alt float::from_str(s) {
-                            some(f) { some(f) }
-                            none { fail("invalid shape_dist_travelled") }
-                        }
-                    }
-                    none { none }
+            if vec::len(lines) == stop_time_chunk || (eof && vec::len(lines) > 0u) {
+                if out_now == limit {
+                    comm::recv(merged);
+                    out_now -= 1u;
                 }
-            };
-            trip_list += [ time ];
-            stop_times.insert(id, trip_list);
-        };
+                comm::send(workers[n % vec::len(workers)], chunk_lines(n, lines));
+                out_now += 1u;
+                n += 1u;
+                lines = [];
+                vec::reserve(lines, stop_time_chunk);
+            }
+            if eof {
+                break;
+            }
+        }
+        for vec::each(workers) { |w|
+            comm::send(w, no_more_chunks);
+        }
+        comm::send(out, loaded_chunk_count(n));
     }
-    fn load_calendars(fname: str, calendars: calendars) {
-        fn getbool(s: str) -> bool {
-            alt(s) {
-                "0" { false }
-                "1" { true }
-                _   { fail("invalid boolean value") }
+}
+
+fn empty_feed() -> feed {
+    {
+        agencies: map::str_hash(),
+        stops: map::str_hash(),
+        routes: map::str_hash(),
+        trips: map::str_hash(),
+        stop_times: map::str_hash(),
+        calendars: map::str_hash(),
+        calendar_dates: map::str_hash(),
+        shapes: map::str_hash(),
+        stop_grid_cache: @{ mut grid: none }
+    }
+}
+
+/* adds one table's worth of records, as sent back by a loader task */
+fn index_loaded(feed: feed, msg: loaded) {
+    alt msg {
+        loaded_agencies(v) {
+            for vec::each(v) { |a| no_overwrite(feed.agencies, a.id, @a); }
+        }
+        loaded_stops(v) {
+            for vec::each(v) { |s| no_overwrite(feed.stops, s.id, @s); }
+        }
+        loaded_routes(v) {
+            for vec::each(v) { |r| no_overwrite(feed.routes, r.id, @r); }
+        }
+        loaded_trips(v) {
+            for vec::each(v) { |t| no_overwrite(feed.trips, t.id, @t); }
+        }
+        loaded_calendars(v) {
+            for vec::each(v) { |c| no_overwrite(feed.calendars, c.service_id, @c); }
+        }
+        loaded_calendar_dates(v) {
+            for vec::each(v) { |c|
+                let mut service_dates = if feed.calendar_dates.contains_key(c.service_id) {
+                    feed.calendar_dates.get(c.service_id)
+                } else {
+                    [mut]
+                };
+                service_dates += [ @c ];
+                feed.calendar_dates.insert(c.service_id, service_dates);
             }
         }
-        enum req { id, mon, tue, wed, thu, fri, sat, sun, start, end };
-        let reqf = [
-            (id as uint, "service_id"),
-            (mon as uint, "monday"),
-            (tue as uint, "tuesday"),
-            (wed as uint, "wednesday"),
-            (thu as uint, "thursday"),
-            (fri as uint, "friday"),
-            (sat as uint, "saturday"),
-            (sun as uint, "sunday"),
-            (start as uint, "start_date"),
-            (end as uint, "end_date")
-                ];
-        let day_enum = [
-            (monday, mon as uint),
-            (tuesday, tue as uint),
-            (wednesday, wed as uint),
-            (thursday, thu as uint),
-            (friday, fri as uint),
-            (saturday, sat as uint),
-            (sunday, sun as uint)
-                ];
-        file_iter(fname, reqf, []) { |row,req,opt|
-            let service_id = row[req[id as uint]];
-            let mut weekdays = [];
-            for vec::each(day_enum) { |tp|
-                let (day, en) = tp;
-                if getbool(row[req[en]]) {
-                    weekdays += [ day ];
-                }
-            };
-            no_overwrite(calendars, service_id, @{
-                service_id: service_id,
-                weekdays: weekdays,
-                start_date: getdate(row[req[start as uint]]),
-                end_date: getdate(row[req[end as uint]])
-            });
-        };
+        loaded_shapes(v) {
+            for vec::each(v) { |s| no_overwrite(feed.shapes, s.id, @s); }
+        }
+        loaded_failed(what) {
+            fail(#fmt("could not load %s", what));
+        }
+        loaded_stop_times(_, _) | loaded_chunk_count(_) {
+            fail("index_loaded: not a table");
+        }
     }
-    fn load_calendar_dates(fname: str, calendar_dates: calendar_dates) {
-        fn get_exception(s: str) -> exception {
-            alt s {
-                "1" { service_added }
-                "2" { service_removed }
-                _ { fail }
+}
+
+/* groups a chunk of stop times (in file order) into runs by trip */
+fn merge_stop_times(stop_times: stop_times, late: late_runs, times: [ stop_time ]) {
+    let mut run_id = "";
+    let mut run : [ mut @stop_time ] = [mut];
+    for vec::each(times) { |t|
+        if t.trip_id != run_id {
+            if vec::len(run) > 0u {
+                sort_by_sequence(run);
+                add_stop_times(stop_times, late, run_id, run);
             }
+            run_id = t.trip_id;
+            run = [mut];
         }
-        enum req { id, date, exception_type }
-        let req = [
-            (id as uint, "service_id"),
-            (date as uint, "date"),
-            (exception_type as uint, "exception_type")
-                ];
-        file_iter(fname, req, []) { |row,req,opt|
-            let service_id = row[req[id as uint]];
-            let mut service_dates = if calendar_dates.contains_key(service_id) {
-                calendar_dates.get(service_id)
-            } else { 
-                [mut]
-            };
-            let calendar_date = @{
-                service_id: service_id,
-                date: getdate(row[req[date as uint]]),
-                exception_type: get_exception(row[req[exception_type as uint]])
-            };
-            service_dates += [ calendar_date ];
-            calendar_dates.insert(service_id, service_dates);
-        };
+        run += [ @t ];
     }
+    if vec::len(run) > 0u {
+        sort_by_sequence(run);
+        add_stop_times(stop_times, late, run_id, run);
+    }
+}
 
-    fn hash_list_sort<T:copy>(m: map::hashmap<str,[mut T]>,
-            lt: fn(T,T) -> bool,
-            eq: fn(T,T) -> bool) {
-        for m.each() { |k,v|
-            sort::quick_sort3(lt, eq, v);
-        };
+/* number of stop_times parsing tasks; LIMERICK_WORKERS overrides */
+fn default_workers() -> uint {
+    alt os::getenv("LIMERICK_WORKERS") {
+        some(s) {
+            alt uint::from_str(s) {
+                some(n) if n > 0u { n }
+                _ { fail("LIMERICK_WORKERS must be a positive integer") }
+            }
+        }
+        none { 4u }
+    }
+}
+
+/* loads everything except stop_times.txt; stop times can then be loaded for
+   just the trips of interest with gtfs_load_stop_times */
+fn gtfs_load_tables(dir: str) -> feed
+{
+    let p = comm::port::<loaded>();
+    spawn_table_loaders(dir, comm::chan(p));
+    let feed = empty_feed();
+    let mut pending = table_count;
+    while pending > 0u {
+        index_loaded(feed, comm::recv(p));
+        pending -= 1u;
     }
+    ret feed;
+}
 
-    let agencies : agencies = map::str_hash();
-    let trips : trips = map::str_hash();
-    let stops : stops = map::str_hash();
-    let routes : routes = map::str_hash();
-    let mut stop_times : stop_times = map::str_hash();
-    let calendars : calendars = map::str_hash();
-    let calendar_dates : calendar_dates = map::str_hash();
-
-    load_agencies(path::connect(dir, "agency.txt"), agencies);
-    load_stops(path::connect(dir, "stops.txt"), stops);
-    load_routes(path::connect(dir, "routes.txt"), routes);
-    load_trips(path::connect(dir, "trips.txt"), trips);
-    load_stop_times(path::connect(dir, "stop_times.txt"), stop_times);
-    /* we can't assume stop times are sorted in input file */
-    hash_list_sort(stop_times,
-            {|v1,v2| v1.sequence < v2.sequence},
-            {|v1,v2| v1.sequence == v2.sequence});
-    load_calendars(path::connect(dir, "calendar.txt"), calendars);
-    load_calendar_dates(path::connect(dir, "calendar_dates.txt"), calendar_dates);
-
-    ret {
-        agencies : agencies,
-        stops: stops,
-        routes: routes, 
-        trips: trips,
-        stop_times: stop_times,
-        calendars: calendars,
-        calendar_dates: calendar_dates
+/* loads stop times for those trips accepted by `want` into the feed */
+fn gtfs_load_stop_times(feed: feed, dir: str, want: fn(trip_id: str) -> bool) {
+    let late : late_runs = map::str_hash();
+    load_stop_times(path::connect(dir, "stop_times.txt")) { |trip_id, times|
+        if want(trip_id) {
+            add_stop_times(feed.stop_times, late, trip_id, times);
+        }
     };
+    merge_late_runs(feed.stop_times, late);
+}
+
+/* streams stop times through `f` one trip at a time, without keeping them;
+   besides the trip being read, only the ids of the trips already seen are
+   held. stop_times.txt must be grouped by trip_id (sort it by trip_id first
+   if it isn't) */
+fn stop_times_each(dir: str, f: fn(trip_id: str, times: [ mut @stop_time ])) {
+    let seen : map::set<str> = map::str_hash();
+    load_stop_times(path::connect(dir, "stop_times.txt")) { |trip_id, times|
+        if seen.contains_key(trip_id) {
+            fail(#fmt("stop_times.txt is not grouped by trip_id (%s seen twice)", trip_id));
+        }
+        map::set_add(seen, trip_id);
+        f(trip_id, times);
+    };
+}
+
+/* loads the whole feed; the tables are each read by their own task, and
+   stop_times.txt is parsed in chunks by `nworkers` tasks. chunks are merged
+   in file order, so the result doesn't depend on which task finishes first.
+   only a couple of chunks per worker are read ahead of the merge */
+fn gtfs_load_parallel(dir: str, nworkers: uint) -> feed
+{
+    let p = comm::port::<loaded>();
+    let ch = comm::chan(p);
+    spawn_table_loaders(dir, ch);
+    let merged = spawn_stop_time_loaders(path::connect(dir, "stop_times.txt"), nworkers,
+                                         2u * uint::max(nworkers, 1u), ch);
+
+    let feed = empty_feed();
+    let held : map::hashmap<uint, [ stop_time ]> = map::uint_hash();
+    let late : late_runs = map::str_hash();
+    let mut tables = 0u;
+    let mut nchunks : option<uint> = none;
+    let mut next_chunk = 0u;
+    loop {
+        alt comm::recv(p) {
+            loaded_stop_times(n, times) {
+                held.insert(n, times);
+            }
+            loaded_chunk_count(n) {
+                nchunks = some(n);
+            }
+            msg {
+                index_loaded(feed, msg);
+                tables += 1u;
+            }
+        }
+        while held.contains_key(next_chunk) {
+            merge_stop_times(feed.stop_times, late, held.get(next_chunk));
+            held.remove(next_chunk);
+            next_chunk += 1u;
+            comm::send(merged, ());
+        }
+        if tables == table_count && nchunks == some(next_chunk) {
+            break;
+        }
+    }
+    merge_late_runs(feed.stop_times, late);
+    ret feed;
+}
+
+fn gtfs_load(dir: str) -> feed
+{
+    ret gtfs_load_parallel(dir, default_workers());
 }
 
 iface feedaccess {
@@ -663,6 +1183,12 @@ iface feedaccess {
     fn stops_for_agency(id: str) -> [ str ];
     fn active_service_ids(day: weekday, date: date) -> [ str ];
     fn trip_ids_for_service_ids(service_ids: [ str ]) -> [ str ];
+    fn stop_index() -> @stop_grid;
+    fn stops_within_radius(pt: point, metres: float) -> [ (@stop, float) ];
+    fn nearest_stops(pt: point, k: uint) -> [ (@stop, float) ];
+    fn stops_in_rect(rect: rectangle) -> [ @stop ];
+    fn station_stop_ids(stop_id: str) -> [ str ];
+    fn departures(stop_id: str, date: date, after: uint, window: uint) -> [ departure ];
 }
 
 fn point_format(point: point) -> str {
@@ -680,6 +1206,10 @@ fn point_format(point: point) -> str {
     ret r;
 }
 
+fn time_format(secs: uint) -> str {
+    #fmt("%02u:%02u:%02u", secs / 3600u, (secs / 60u) % 60u, secs % 60u)
+}
+
 fn lookup_list<K: copy>(map: map::hashmap<str, K>, keys: [str]) -> [ K ] {
     let mut r = [];
     vec::reserve(r, map.size());
@@ -761,11 +1291,19 @@ impl of feedaccess for feed {
             ne : { lat: lat_max, lon: lon_max }
         }
     }
+    /* services running on `date`: calendars only count between their
+       start_date and end_date (dates outside that range used to match on
+       the weekday alone), then calendar_dates exceptions apply */
     fn active_service_ids(day: weekday, date: date) -> [ str ] {
         let active : map::set<str> = map::str_hash();
         // first, we got through and find all the calendar events 
         // active on this week day
+        let today = date_ordinal(date);
         for self.calendars.each_value() { |calendar|
+            if today < date_ordinal(calendar.start_date) ||
+                    today > date_ordinal(calendar.end_date) {
+                cont;
+            }
             alt vec::position_elem(calendar.weekdays, day) {
                 some(d) { 
                     map::set_add(active, calendar.service_id);
@@ -816,4 +1354,104 @@ impl of feedaccess for feed {
         }
         self.stops_bbox(stops)
     }
+    fn stop_index() -> @stop_grid {
+        alt self.stop_grid_cache.grid {
+            some(grid) { grid }
+            none {
+                let mut stops = [];
+                vec::reserve(stops, self.stops.size());
+                for self.stops.each_value() { |stop|
+                    stops += [ stop ];
+                }
+                let grid = @spatial::build_grid(stops, self.stops_bbox(stops));
+                self.stop_grid_cache.grid = some(grid);
+                grid
+            }
+        }
+    }
+    fn stops_within_radius(pt: point, metres: float) -> [ (@stop, float) ] {
+        ret spatial::within_radius(*self.stop_index(), pt, metres);
+    }
+    fn nearest_stops(pt: point, k: uint) -> [ (@stop, float) ] {
+        ret spatial::nearest(*self.stop_index(), pt, k);
+    }
+    fn stops_in_rect(rect: rectangle) -> [ @stop ] {
+        ret spatial::within_rect(*self.stop_index(), rect);
+    }
+    /* the stop, and if it's a station, the stops within it */
+    fn station_stop_ids(stop_id: str) -> [ str ] {
+        let mut ids = [ stop_id ];
+        for self.stops.each_value() { |stop|
+            alt stop.parent_station {
+                some(parent) if parent == stop_id {
+                    ids += [ stop.id ];
+                }
+                _ {}
+            }
+        }
+        ret ids;
+    }
+    /* departures from the stop (or any stop in the station) in
+       [after, after + window) on the given date, in time order. trips
+       belonging to the previous day's service which are still running after
+       midnight are included. the last stop of a trip isn't a departure, nor
+       is a stop where pickup isn't available */
+    fn departures(stop_id: str, date: date, after: uint, window: uint) -> [ departure ] {
+        let at_stop : map::set<str> = map::str_hash();
+        for vec::each(self.station_stop_ids(stop_id)) { |id|
+            map::set_add(at_stop, id);
+        }
+        fn nonempty(s: option<str>) -> option<str> {
+            alt s {
+                some(v) if v != "" { some(v) }
+                _ { none }
+            }
+        }
+        let mut res = [mut];
+        /* service days are offset from the requested day by `offset` seconds */
+        for vec::each([ (date, 0u), (date_add_days(date, -1), 86400u) ]) { |day_offset|
+            let (service_date, offset) = day_offset;
+            let service_ids = self.active_service_ids(weekday_of(service_date), service_date);
+            for vec::each(self.trip_ids_for_service_ids(service_ids)) { |trip_id|
+                let times = alt self.stop_times.find(trip_id) {
+                    some(times) { times }
+                    none { cont; }
+                };
+                let last = vec::len(times) - 1u;
+                for vec::eachi(times) { |i, st|
+                    if i == last || !at_stop.contains_key(st.stop_id) || st.departure_time < offset {
+                        cont;
+                    }
+                    let time = st.departure_time - offset;
+                    if time < after || time >= after + window {
+                        cont;
+                    }
+                    let pickup = option::get_default(st.pickup_type, scheduled);
+                    if pickup == nopickup {
+                        cont;
+                    }
+                    let trip = self.trips.get(trip_id);
+                    let headsign = alt nonempty(st.headsign) {
+                        some(h) { some(h) }
+                        none { nonempty(trip.headsign) }
+                    };
+                    res += [ {
+                        time: time,
+                        stop: self.stops.get(st.stop_id),
+                        route: self.routes.get(trip.route_id),
+                        trip: trip,
+                        stop_time: st,
+                        headsign: headsign,
+                        pickup: pickup
+                    } ];
+                }
+            }
+        }
+        sort::quick_sort3({|a, b|
+            a.time < b.time || (a.time == b.time && a.route.short_name < b.route.short_name)
+        }, {|a, b|
+            a.time == b.time && a.route.short_name == b.route.short_name
+        }, res);
+        ret vec::from_mut(res);
+    }
 }
@@ -0,0 +1,137 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import std::sort;
+import gtfs::{feed, feedaccess, date, weekday_of, time_format};
+import report::{csv_row};
+
+export service_level, service_levels, levels_csv;
+
+/*
+ * levels of service over one service day: for each stop, and for each
+ * route at each stop, how often something departs. times are seconds after
+ * midnight on the service day, so can run past 24:00.
+ */
+
+type service_level = {
+    stop_id: str,
+    /* none for all routes at the stop together */
+    route_id: option<str>,
+    departures: uint,
+    first: uint,
+    last: uint,
+    /* none if there are fewer than two departures */
+    mean_headway: option<uint>,
+    max_headway: option<uint>,
+    /* departures in each hour, from midnight */
+    per_hour: [ uint ]
+};
+
+fn level_for(stop_id: str, route_id: option<str>, times: [ uint ]) -> service_level {
+    let sorted = sort::merge_sort({|a, b| a <= b }, times);
+    let n = vec::len(sorted);
+    let first = sorted[0], last = sorted[n - 1u];
+    let mut per_hour = vec::to_mut(vec::from_elem(last / 3600u + 1u, 0u));
+    for vec::each(sorted) { |t|
+        per_hour[t / 3600u] += 1u;
+    }
+    let mut max_gap = 0u;
+    let mut i = 1u;
+    while i < n {
+        max_gap = uint::max(max_gap, sorted[i] - sorted[i - 1u]);
+        i += 1u;
+    }
+    {
+        stop_id: stop_id,
+        route_id: route_id,
+        departures: n,
+        first: first,
+        last: last,
+        mean_headway: if n > 1u { some((last - first) / (n - 1u)) } else { none },
+        max_headway: if n > 1u { some(max_gap) } else { none },
+        per_hour: vec::from_mut(per_hour)
+    }
+}
+
+/* service levels for every stop with a departure on the date, for all
+   routes and then route by route, ordered by stop and route */
+fn service_levels(feed: feed, date: date) -> [ service_level ] {
+    let service_ids = feed.active_service_ids(weekday_of(date), date);
+    let by_stop : map::hashmap<str, [ mut uint ]> = map::str_hash();
+    let by_route_stop : map::hashmap<str, [ mut uint ]> = map::str_hash();
+    fn add(m: map::hashmap<str, [ mut uint ]>, k: str, t: uint) {
+        let mut v = alt m.find(k) {
+            some(v) { v }
+            none { [mut] }
+        };
+        v += [ t ];
+        m.insert(k, v);
+    }
+    for vec::each(feed.trip_ids_for_service_ids(service_ids)) { |trip_id|
+        let trip = feed.trips.get(trip_id);
+        let times = alt feed.stop_times.find(trip_id) {
+            some(times) { times }
+            none { cont; }
+        };
+        /* nothing departs from the last stop */
+        let last = vec::len(times) - 1u;
+        for vec::eachi(times) { |i, st|
+            if i == last {
+                break;
+            }
+            add(by_stop, st.stop_id, st.departure_time);
+            add(by_route_stop, st.stop_id + "\x1f" + trip.route_id, st.departure_time);
+        }
+    }
+
+    let mut res = [mut];
+    for by_stop.each() { |stop_id, times|
+        res += [ level_for(stop_id, none, vec::from_mut(times)) ];
+    }
+    for by_route_stop.each() { |key, times|
+        let parts = str::split_char(key, '\x1f');
+        res += [ level_for(parts[0], some(parts[1]), vec::from_mut(times)) ];
+    }
+    sort::quick_sort3({|a, b|
+        (a.stop_id, a.route_id) < (b.stop_id, b.route_id)
+    }, {|a, b|
+        a.stop_id == b.stop_id && a.route_id == b.route_id
+    }, res);
+    ret vec::from_mut(res);
+}
+
+fn minutes(t: option<uint>) -> str {
+    alt t {
+        some(secs) { #fmt("%.1f", (secs as float) / 60.) }
+        none { "" }
+    }
+}
+
+/* one row per service level, with a column per hour */
+fn levels_csv(feed: feed, levels: [ service_level ]) -> str {
+    let mut hours = 0u;
+    for vec::each(levels) { |l|
+        hours = uint::max(hours, vec::len(l.per_hour));
+    }
+    let mut header = [ "stop_id", "stop_name", "route_id", "route_short_name", "departures",
+                       "first_departure", "last_departure", "mean_headway_mins", "max_headway_mins" ];
+    for uint::range(0u, hours) { |h|
+        header += [ #fmt("h%02u", h) ];
+    }
+    let mut res = csv_row(header) + "\n";
+    for vec::each(levels) { |l|
+        let (route_id, short_name) = alt l.route_id {
+            some(id) { (id, feed.routes.get(id).short_name) }
+            none { ("", "") }
+        };
+        let mut row = [ l.stop_id, feed.stops.get(l.stop_id).name, route_id, short_name,
+                        uint::str(l.departures), time_format(l.first), time_format(l.last),
+                        minutes(l.mean_headway), minutes(l.max_headway) ];
+        for uint::range(0u, hours) { |h|
+            row += [ uint::str(if h < vec::len(l.per_hour) { l.per_hour[h] } else { 0u }) ];
+        }
+        res += csv_row(row) + "\n";
+    }
+    ret res;
+}
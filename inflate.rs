@@ -0,0 +1,270 @@
+export zlib_inflate, inflate;
+
+/*
+ * a plain deflate decoder (RFC 1951) with the zlib wrapper (RFC 1950),
+ * enough to read the compressed blocks of an OSM PBF file. it follows the
+ * canonical-code approach of zlib's puff.c: slow, but small.
+ */
+
+const max_bits : uint = 15u;
+
+type state = @{
+    data: [ u8 ],
+    mut pos: uint,
+    mut bitbuf: uint,
+    mut bitcnt: uint
+};
+
+/* a canonical huffman code: how many codes of each length, and the
+   symbols in code order */
+type huffman = {
+    counts: [ uint ],
+    symbols: [ uint ]
+};
+
+fn length_base() -> [ uint ] {
+    [ 3u, 4u, 5u, 6u, 7u, 8u, 9u, 10u, 11u, 13u, 15u, 17u, 19u, 23u, 27u, 31u,
+      35u, 43u, 51u, 59u, 67u, 83u, 99u, 115u, 131u, 163u, 195u, 227u, 258u ]
+}
+
+fn length_extra() -> [ uint ] {
+    [ 0u, 0u, 0u, 0u, 0u, 0u, 0u, 0u, 1u, 1u, 1u, 1u, 2u, 2u, 2u, 2u,
+      3u, 3u, 3u, 3u, 4u, 4u, 4u, 4u, 5u, 5u, 5u, 5u, 0u ]
+}
+
+fn dist_base() -> [ uint ] {
+    [ 1u, 2u, 3u, 4u, 5u, 7u, 9u, 13u, 17u, 25u, 33u, 49u, 65u, 97u, 129u,
+      193u, 257u, 385u, 513u, 769u, 1025u, 1537u, 2049u, 3073u, 4097u,
+      6145u, 8193u, 12289u, 16385u, 24577u ]
+}
+
+fn dist_extra() -> [ uint ] {
+    [ 0u, 0u, 0u, 0u, 1u, 1u, 2u, 2u, 3u, 3u, 4u, 4u, 5u, 5u, 6u, 6u,
+      7u, 7u, 8u, 8u, 9u, 9u, 10u, 10u, 11u, 11u, 12u, 12u, 13u, 13u ]
+}
+
+/* the next `need` bits, least significant first */
+fn bits(s: state, need: uint) -> uint {
+    let mut val = s.bitbuf;
+    while s.bitcnt < need {
+        if s.pos >= vec::len(s.data) {
+            fail("inflate: ran out of input");
+        }
+        val |= (s.data[s.pos] as uint) << s.bitcnt;
+        s.pos += 1u;
+        s.bitcnt += 8u;
+    }
+    s.bitbuf = val >> need;
+    s.bitcnt -= need;
+    ret val & ((1u << need) - 1u);
+}
+
+fn construct(lengths: [ uint ]) -> huffman {
+    let mut counts = vec::to_mut(vec::from_elem(max_bits + 1u, 0u));
+    for vec::each(lengths) { |l| counts[l] += 1u; }
+    let mut offs = vec::to_mut(vec::from_elem(max_bits + 1u, 0u));
+    for uint::range(1u, max_bits) { |l|
+        offs[l + 1u] = offs[l] + counts[l];
+    }
+    let mut symbols = vec::to_mut(vec::from_elem(vec::len(lengths), 0u));
+    for vec::eachi(lengths) { |sym, l|
+        if l != 0u {
+            symbols[offs[l]] = sym;
+            offs[l] += 1u;
+        }
+    }
+    ret { counts: vec::from_mut(counts), symbols: vec::from_mut(symbols) };
+}
+
+fn decode(s: state, h: huffman) -> uint {
+    let mut code = 0u;
+    let mut first = 0u;
+    let mut index = 0u;
+    let mut len = 1u;
+    while len <= max_bits {
+        code |= bits(s, 1u);
+        let count = h.counts[len];
+        if code < first + count {
+            ret h.symbols[index + code - first];
+        }
+        index += count;
+        first = (first + count) << 1u;
+        code <<= 1u;
+        len += 1u;
+    }
+    fail("inflate: bad huffman code");
+}
+
+fn stored(s: state, &out: [ u8 ]) {
+    /* skip to the byte boundary */
+    s.bitbuf = 0u;
+    s.bitcnt = 0u;
+    if s.pos + 4u > vec::len(s.data) {
+        fail("inflate: ran out of input");
+    }
+    let len = (s.data[s.pos] as uint) | ((s.data[s.pos + 1u] as uint) << 8u);
+    let nlen = (s.data[s.pos + 2u] as uint) | ((s.data[s.pos + 3u] as uint) << 8u);
+    if len != (!nlen & 0xffffu) {
+        fail("inflate: stored block length mismatch");
+    }
+    s.pos += 4u;
+    if s.pos + len > vec::len(s.data) {
+        fail("inflate: ran out of input");
+    }
+    out += vec::slice(s.data, s.pos, s.pos + len);
+    s.pos += len;
+}
+
+fn codes(s: state, &out: [ u8 ], lencode: huffman, distcode: huffman) {
+    let lbase = length_base();
+    let lext = length_extra();
+    let dbase = dist_base();
+    let dext = dist_extra();
+    loop {
+        let sym = decode(s, lencode);
+        if sym < 256u {
+            out += [ sym as u8 ];
+        } else if sym == 256u {
+            ret;
+        } else {
+            let sym = sym - 257u;
+            if sym >= 29u {
+                fail("inflate: bad length symbol");
+            }
+            let len = lbase[sym] + bits(s, lext[sym]);
+            let dsym = decode(s, distcode);
+            if dsym >= 30u {
+                fail("inflate: bad distance symbol");
+            }
+            let dist = dbase[dsym] + bits(s, dext[dsym]);
+            if dist > vec::len(out) {
+                fail("inflate: distance too far back");
+            }
+            /* byte at a time, as the copy may overlap what it's writing */
+            let mut i = 0u;
+            while i < len {
+                out += [ out[vec::len(out) - dist] ];
+                i += 1u;
+            }
+        }
+    }
+}
+
+fn fixed(s: state, &out: [ u8 ]) {
+    let lengths = vec::from_fn(288u) { |sym|
+        if sym < 144u { 8u } else if sym < 256u { 9u } else if sym < 280u { 7u } else { 8u }
+    };
+    codes(s, out, construct(lengths), construct(vec::from_elem(30u, 5u)));
+}
+
+fn dynamic(s: state, &out: [ u8 ]) {
+    let order = [ 16u, 17u, 18u, 0u, 8u, 7u, 9u, 6u, 10u, 5u, 11u, 4u, 12u, 3u, 13u, 2u, 14u, 1u, 15u ];
+    let nlen = bits(s, 5u) + 257u;
+    let ndist = bits(s, 5u) + 1u;
+    let ncode = bits(s, 4u) + 4u;
+    if nlen > 286u || ndist > 30u {
+        fail("inflate: bad code counts");
+    }
+    let mut clens = vec::to_mut(vec::from_elem(19u, 0u));
+    for uint::range(0u, ncode) { |i|
+        clens[order[i]] = bits(s, 3u);
+    }
+    let lencode = construct(vec::from_mut(clens));
+
+    let mut lengths = [];
+    while vec::len(lengths) < nlen + ndist {
+        let sym = decode(s, lencode);
+        if sym < 16u {
+            lengths += [ sym ];
+        } else {
+            let (value, repeat) = alt sym {
+                16u {
+                    if vec::len(lengths) == 0u {
+                        fail("inflate: repeat with no previous length");
+                    }
+                    (vec::last(lengths), 3u + bits(s, 2u))
+                }
+                17u { (0u, 3u + bits(s, 3u)) }
+                _ { (0u, 11u + bits(s, 7u)) }
+            };
+            if vec::len(lengths) + repeat > nlen + ndist {
+                fail("inflate: too many lengths");
+            }
+            lengths += vec::from_elem(repeat, value);
+        }
+    }
+    codes(s, out, construct(vec::slice(lengths, 0u, nlen)),
+          construct(vec::slice(lengths, nlen, nlen + ndist)));
+}
+
+/* decompresses raw deflate data */
+fn inflate(data: [ u8 ]) -> [ u8 ] {
+    let s = @{ data: data, mut pos: 0u, mut bitbuf: 0u, mut bitcnt: 0u };
+    let mut out = [];
+    loop {
+        let last = bits(s, 1u);
+        alt bits(s, 2u) {
+            0u { stored(s, out); }
+            1u { fixed(s, out); }
+            2u { dynamic(s, out); }
+            _ { fail("inflate: bad block type"); }
+        }
+        if last == 1u {
+            break;
+        }
+    }
+    ret out;
+}
+
+/* decompresses zlib-wrapped deflate data; the checksum isn't checked */
+fn zlib_inflate(data: [ u8 ]) -> [ u8 ] {
+    if vec::len(data) < 2u || data[0] & 0x0fu8 != 8u8
+        || ((data[0] as uint) * 256u + (data[1] as uint)) % 31u != 0u {
+        fail("inflate: not zlib data");
+    }
+    if data[1] & 0x20u8 != 0u8 {
+        fail("inflate: preset dictionaries aren't supported");
+    }
+    inflate(vec::slice(data, 2u, vec::len(data)))
+}
+
+#[cfg(test)]
+mod tests {
+    import inflate::{inflate, zlib_inflate};
+
+    fn check(data: [ u8 ], expected: str) {
+        assert(str::from_bytes(inflate(data)) == expected);
+    }
+
+    #[test]
+    fn stored_block() {
+        check([ 0x01u8, 0x0cu8, 0x00u8, 0xf3u8, 0xffu8, 0x73u8, 0x74u8, 0x6fu8, 0x72u8, 0x65u8,
+                0x64u8, 0x20u8, 0x62u8, 0x6cu8, 0x6fu8, 0x63u8, 0x6bu8 ],
+              "stored block");
+    }
+
+    #[test]
+    fn fixed_block() {
+        check([ 0x4bu8, 0x4cu8, 0x4au8, 0x4eu8, 0x84u8, 0x21u8, 0x85u8, 0xb4u8, 0xccu8, 0x8au8,
+                0xd4u8, 0x14u8, 0x00u8 ],
+              "abcabcabcabc fixed");
+    }
+
+    #[test]
+    fn dynamic_block() {
+        check([ 0x15u8, 0xc4u8, 0xc1u8, 0x09u8, 0x00u8, 0x30u8, 0x08u8, 0x04u8, 0xc1u8, 0x56u8,
+                0x6cu8, 0xedu8, 0x20u8, 0x0bu8, 0x3eu8, 0x44u8, 0x21u8, 0x5eu8, 0xffu8, 0x84u8,
+                0xccu8, 0x63u8, 0x20u8, 0x3cu8, 0x14u8, 0x81u8, 0xf3u8, 0xb4u8, 0x76u8, 0x47u8,
+                0xc6u8, 0x84u8, 0x6eu8, 0xaau8, 0x0du8, 0xd9u8, 0xe8u8, 0x47u8, 0x85u8, 0xa4u8,
+                0x5cu8, 0x78u8 ],
+              "ee toele ethdnassoatete arhanteehneaehneel aaahsee");
+    }
+
+    #[test]
+    fn zlib_wrapped() {
+        let data = [ 0x78u8, 0xdau8, 0xabu8, 0xcau8, 0xc9u8, 0x4cu8, 0x52u8, 0x28u8, 0x2fu8, 0x4au8,
+                     0x2cu8, 0x28u8, 0x48u8, 0x4du8, 0x51u8, 0xa8u8, 0x42u8, 0xe2u8, 0x00u8, 0x00u8,
+                     0x7du8, 0x9du8, 0x09u8, 0xa9u8 ];
+        assert(str::from_bytes(zlib_inflate(data)) == "zlib wrapped zlib wrapped");
+    }
+}
@@ -0,0 +1,317 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::{feed, feedaccess, stop, point, time_format};
+import csa::{timetable, stop_lookup, earliest_arrivals};
+import transfer::{walking, walk_secs};
+import geom::{projection, local_projection, project, unproject};
+import report::{csv_row};
+
+export travel_times, arrivals_csv, contours_geojson;
+
+/*
+ * travel times from a point to everywhere: walk to the stops in reach,
+ * ride, and (for the areas in reach) walk on from wherever you get off.
+ * for the map, the walks on are laid over a grid of squares, and each
+ * band's contour is the outline of the squares reached within it.
+ */
+
+/* side of the grid squares, in metres */
+const cell_metres : float = 100.;
+
+/* directions along the grid lines, anticlockwise from east */
+const east : uint = 0u;
+const north : uint = 1u;
+const west : uint = 2u;
+const south : uint = 3u;
+
+/* earliest arrival at each stop of the timetable, leaving at `depart` and
+   walking first to the stops in `access`, with the seconds each takes;
+   uint::max_value where it can't be reached by `until` */
+fn travel_times(tt: timetable, access: [ (@stop, uint) ], depart: uint, until: uint,
+                change: uint) -> [ uint ] {
+    let stops = stop_lookup(tt);
+    let mut sources = [];
+    for vec::each(access) { |walk|
+        let (stop, secs) = walk;
+        alt stops.find(stop.id) {
+            some(s) { sources += [ (s, depart + secs) ]; }
+            none {}
+        }
+    }
+    ret earliest_arrivals(tt, sources, until, change);
+}
+
+fn arrivals_csv(feed: feed, tt: timetable, depart: uint, arrivals: [ uint ]) -> str {
+    let mut res = csv_row([ "stop_id", "stop_name", "stop_lat", "stop_lon", "arrival", "minutes" ]) + "\n";
+    for vec::eachi(arrivals) { |s, a|
+        if a == uint::max_value {
+            cont;
+        }
+        let stop = feed.stops.get(tt.stop_ids[s]);
+        res += csv_row([ stop.id, stop.name, #fmt("%.6f", stop.pt.lat), #fmt("%.6f", stop.pt.lon),
+                         time_format(a), uint::str((a - depart + 59u) / 60u) ]) + "\n";
+    }
+    ret res;
+}
+
+/* seconds from departure to the centre of each square of a grid over
+   everywhere reachable, row by row from the south-west corner at (x0, y0),
+   or uint::max_value where it can't be reached */
+type reach_grid = {
+    proj: projection,
+    x0: float,
+    y0: float,
+    rows: uint,
+    cols: uint,
+    secs: [ uint ]
+};
+
+/* walks on, as the crow flies, from the origin and from each stop reached
+   within `limit` seconds of departure */
+fn fill_grid(feed: feed, tt: timetable, origin: point, depart: uint, arrivals: [ uint ],
+             limit: uint, w: walking) -> reach_grid {
+    let proj = local_projection(origin);
+    /* where each walk starts, and when */
+    let mut sources = [ (0., 0., 0u) ];
+    for vec::eachi(arrivals) { |s, a|
+        if a < depart + limit {
+            let (x, y) = project(proj, feed.stops.get(tt.stop_ids[s]).pt);
+            sources += [ (x, y, a - depart) ];
+        }
+    }
+    let mut xmin = 0.;
+    let mut xmax = 0.;
+    let mut ymin = 0.;
+    let mut ymax = 0.;
+    for vec::each(sources) { |src|
+        let (x, y, _) = src;
+        xmin = float::fmin(xmin, x);
+        xmax = float::fmax(xmax, x);
+        ymin = float::fmin(ymin, y);
+        ymax = float::fmax(ymax, y);
+    }
+    /* a square's width spare all round, so contours close inside the grid */
+    let margin = w.radius + cell_metres;
+    let x0 = xmin - margin;
+    let y0 = ymin - margin;
+    let cols = float::ceil((xmax + margin - x0) / cell_metres) as uint;
+    let rows = float::ceil((ymax + margin - y0) / cell_metres) as uint;
+    let secs = vec::to_mut(vec::from_elem(rows * cols, uint::max_value));
+    let span = float::ceil(w.radius / cell_metres) as int;
+    for vec::each(sources) { |src|
+        let (x, y, start) = src;
+        let row = float::floor((y - y0) / cell_metres) as int;
+        let col = float::floor((x - x0) / cell_metres) as int;
+        let mut r = int::max(0, row - span);
+        while r <= int::min(rows as int - 1, row + span) {
+            let mut c = int::max(0, col - span);
+            while c <= int::min(cols as int - 1, col + span) {
+                let dx = x0 + ((c as float) + 0.5) * cell_metres - x;
+                let dy = y0 + ((r as float) + 0.5) * cell_metres - y;
+                let d = float::sqrt(dx * dx + dy * dy);
+                if d <= w.radius {
+                    let k = (r as uint) * cols + (c as uint);
+                    secs[k] = uint::min(secs[k], start + walk_secs(w, d));
+                }
+                c += 1;
+            }
+            r += 1;
+        }
+    }
+    { proj: proj, x0: x0, y0: y0, rows: rows, cols: cols, secs: vec::from_mut(secs) }
+}
+
+fn reached(g: reach_grid, secs: uint, r: int, c: int) -> bool {
+    r >= 0 && c >= 0 && r < g.rows as int && c < g.cols as int &&
+        g.secs[(r as uint) * g.cols + (c as uint)] <= secs
+}
+
+/* corner `v` of the grid squares, numbered row by row with cols + 1 to a row */
+fn corner(g: reach_grid, v: uint) -> point {
+    let i = v / (g.cols + 1u);
+    let j = v % (g.cols + 1u);
+    unproject(g.proj, g.x0 + (j as float) * cell_metres, g.y0 + (i as float) * cell_metres)
+}
+
+fn step(g: reach_grid, v: uint, d: uint) -> uint {
+    alt d {
+        0u { v + 1u }
+        1u { v + g.cols + 1u }
+        2u { v - 1u }
+        _ { v - g.cols - 1u }
+    }
+}
+
+/* the square on the left of the grid line leaving corner `v` heading `d` */
+fn left_square(g: reach_grid, v: uint, d: uint) -> uint {
+    let i = v / (g.cols + 1u);
+    let j = v % (g.cols + 1u);
+    alt d {
+        0u { i * g.cols + j }
+        1u { i * g.cols + j - 1u }
+        2u { (i - 1u) * g.cols + j - 1u }
+        _ { (i - 1u) * g.cols + j }
+    }
+}
+
+/* where the outline goes on from a corner reached heading `d`. where two
+   squares touch only at a corner, turning left keeps them apart */
+fn turn(ways: uint, d: uint) -> uint {
+    for vec::each([ (d + 1u) % 4u, d, (d + 3u) % 4u ]) { |nd|
+        if ways & (1u << nd) != 0u {
+            ret nd;
+        }
+    }
+    fail("isochrone: outline runs into a dead end");
+}
+
+/* twice the signed area, positive for an anticlockwise ring */
+fn ring_area(ring: [ point ]) -> float {
+    let mut a = 0.;
+    let mut i = 1u;
+    while i < vec::len(ring) {
+        a += ring[i - 1u].lon * ring[i].lat - ring[i].lon * ring[i - 1u].lat;
+        i += 1u;
+    }
+    ret a;
+}
+
+/* the outline of the squares reached within `secs`, as polygons: one for
+   each patch of squares joined edge to edge, its outer ring anticlockwise
+   and then its holes clockwise */
+fn contours(g: reach_grid, secs: uint) -> [ [ [ point ] ] ] {
+    let n = g.rows * g.cols;
+    let none_yet = uint::max_value;
+
+    /* number the patches */
+    let patch = vec::to_mut(vec::from_elem(n, none_yet));
+    let todo = vec::to_mut(vec::from_elem(n, 0u));
+    let mut npatches = 0u;
+    for uint::range(0u, n) { |k|
+        if patch[k] != none_yet || g.secs[k] > secs {
+            cont;
+        }
+        patch[k] = npatches;
+        todo[0] = k;
+        let mut top = 1u;
+        while top > 0u {
+            top -= 1u;
+            let q = todo[top];
+            let r = (q / g.cols) as int;
+            let c = (q % g.cols) as int;
+            for vec::each([ (r - 1, c), (r + 1, c), (r, c - 1), (r, c + 1) ]) { |rc|
+                let (nr, nc) = rc;
+                if reached(g, secs, nr, nc) {
+                    let m = (nr as uint) * g.cols + (nc as uint);
+                    if patch[m] == none_yet {
+                        patch[m] = npatches;
+                        todo[top] = m;
+                        top += 1u;
+                    }
+                }
+            }
+        }
+        npatches += 1u;
+    }
+
+    /* the grid lines between squares reached and squares not, each
+       heading so the square reached is on its left */
+    let vcols = g.cols + 1u;
+    let ways = vec::to_mut(vec::from_elem((g.rows + 1u) * vcols, 0u));
+    for uint::range(0u, n) { |k|
+        if patch[k] == none_yet {
+            cont;
+        }
+        let r = (k / g.cols) as int;
+        let c = (k % g.cols) as int;
+        let sw = (r as uint) * vcols + (c as uint);
+        if !reached(g, secs, r - 1, c) { ways[sw] |= 1u << east; }
+        if !reached(g, secs, r, c + 1) { ways[sw + 1u] |= 1u << north; }
+        if !reached(g, secs, r + 1, c) { ways[sw + vcols + 1u] |= 1u << west; }
+        if !reached(g, secs, r, c - 1) { ways[sw + vcols] |= 1u << south; }
+    }
+
+    /* follow them round into rings. the first corner met of each ring is
+       its lowest leftmost, which is always a turn. where a ring comes back
+       to a corner it has passed, at two squares touching only there, the
+       loop since is split off so every ring is simple */
+    let open_at = vec::to_mut(vec::from_elem(vec::len(ways), none_yet));
+    let mut rings = [];
+    for uint::range(0u, vec::len(ways)) { |start|
+        while ways[start] != 0u {
+            let mut d = east;
+            while ways[start] & (1u << d) == 0u {
+                d += 1u;
+            }
+            let p = patch[left_square(g, start, d)];
+            let mut open = [ start ];
+            open_at[start] = 0u;
+            let mut v = start;
+            loop {
+                ways[v] &= !(1u << d);
+                let next = step(g, v, d);
+                if next == start {
+                    break;
+                }
+                let nd = turn(ways[next], d);
+                if nd != d {
+                    let k = open_at[next];
+                    if k == none_yet {
+                        open_at[next] = vec::len(open);
+                        open += [ next ];
+                    } else {
+                        let len = vec::len(open);
+                        for vec::each(vec::slice(open, k + 1u, len)) { |u| open_at[u] = none_yet; }
+                        rings += [ (p, vec::slice(open, k, len) + [ next ]) ];
+                        open = vec::slice(open, 0u, k + 1u);
+                    }
+                }
+                v = next;
+                d = nd;
+            }
+            for vec::each(open) { |u| open_at[u] = none_yet; }
+            rings += [ (p, open + [ start ]) ];
+        }
+    }
+
+    let outer = vec::to_mut(vec::from_elem(npatches, []));
+    let holes = vec::to_mut(vec::from_elem(npatches, []));
+    for vec::each(rings) { |pr|
+        let (p, ids) = pr;
+        let ring = vec::map(ids) { |u| corner(g, u) };
+        if ring_area(ring) > 0. {
+            outer[p] = ring;
+        } else {
+            holes[p] += [ ring ];
+        }
+    }
+    vec::from_fn(npatches) { |p| [ outer[p] ] + holes[p] }
+}
+
+fn ring_json(ring: [ point ]) -> str {
+    "[" + str::connect(vec::map(ring) { |pt| #fmt("[%.6f,%.6f]", pt.lon, pt.lat) }, ",") + "]"
+}
+
+/* a GeoJSON FeatureCollection with a MultiPolygon for each band of
+   minutes, covering everywhere reachable within it: by walking from the
+   origin, or riding and walking on from a stop, as the crow flies. the
+   outlines follow a grid of cell_metres squares. largest band first, so
+   the smaller bands draw on top */
+fn contours_geojson(feed: feed, tt: timetable, origin: point, depart: uint,
+                    arrivals: [ uint ], bands: [ uint ], w: walking) -> str {
+    let sorted = std::sort::merge_sort({|a, b| a >= b }, bands);
+    let mut features = [];
+    if vec::len(sorted) > 0u {
+        let g = fill_grid(feed, tt, origin, depart, arrivals, sorted[0] * 60u, w);
+        for vec::each(sorted) { |minutes|
+            let polys = vec::map(contours(g, minutes * 60u)) { |rings|
+                "[" + str::connect(vec::map(rings, ring_json), ",") + "]"
+            };
+            features += [ #fmt("{\"type\":\"Feature\",\"properties\":{\"minutes\":%u},\"geometry\":{\"type\":\"MultiPolygon\",\"coordinates\":[%s]}}",
+                               minutes, str::connect(polys, ",")) ];
+        }
+    }
+    "{\"type\":\"FeatureCollection\",\"features\":[\n" + str::connect(features, ",\n") + "\n]}\n"
+}
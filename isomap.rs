@@ -0,0 +1,63 @@
+
+use gtfs;
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::gtfs_load;
+import gtfs::{feedaccess};
+import gtfs::csa::{build_timetable};
+import gtfs::transfer::{walking_transfers, default_walking, crow_walks, merge_transfers,
+                        load_feed_transfers, station_walk, min_change};
+import gtfs::osm::{load_osm, walkable};
+import gtfs::streets::{street_network, street_walks, street_transfers};
+import gtfs::isochrone::{travel_times, arrivals_csv, contours_geojson};
+import gtfs::report::{write_file};
+
+fn usage() {
+    io::println("usage: isomap <gtfs dir> <lat> <lon> <YYYY-MM-DD> <HH:MM> <csv out> <geojson out> [minutes,minutes,... [osm file]]");
+    io::println("       with an OSM extract (.osm or .osm.pbf), walks follow the streets");
+}
+
+fn main(args: [str])
+{
+    if vec::len(args) < 8u || vec::len(args) > 10u {
+        usage();
+        ret;
+    }
+    let num = { |s: str|
+        alt float::from_str(s) {
+            some(f) { f }
+            none { fail(#fmt("not a number: %s", s)) }
+        }
+    };
+    let origin = { lat: num(args[2]), lon: num(args[3]) };
+    let date = gtfs::parse_date(args[4]);
+    let depart = gtfs::gettime(args[5] + ":00");
+    let bands = if vec::len(args) > 8u {
+        vec::map(str::split_char(args[8], ',')) { |s|
+            alt uint::from_str(s) {
+                some(n) { n }
+                none { fail(#fmt("not a number of minutes: %s", s)) }
+            }
+        }
+    } else {
+        [ 15u, 30u, 45u, 60u ]
+    };
+    let mut longest = 0u;
+    for vec::each(bands) { |b| longest = uint::max(longest, b); }
+
+    let feed = gtfs_load(args[1]);
+    let walking = default_walking();
+    let (transfers, access) = if vec::len(args) == 10u {
+        let s = street_network(feed, load_osm(args[9], walkable));
+        (street_transfers(feed, s, walking, some(station_walk)), street_walks(feed, s, origin, walking))
+    } else {
+        (walking_transfers(feed, walking, some(station_walk)), crow_walks(feed, walking, origin))
+    };
+    merge_transfers(transfers, load_feed_transfers(feed, args[1], walking));
+    let tt = build_timetable(feed, date, transfers);
+    let arrivals = travel_times(tt, access, depart, depart + longest * 60u, min_change);
+    write_file(args[6], arrivals_csv(feed, tt, depart, arrivals));
+    write_file(args[7], contours_geojson(feed, tt, origin, depart, arrivals, bands, walking));
+}
@@ -0,0 +1,67 @@
+
+use gtfs;
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::gtfs_load;
+import gtfs::{feedaccess};
+import gtfs::raptor::{build_network, plan, default_max_transfers};
+import gtfs::transfer::{walking_transfers, default_walking, merge_transfers,
+                        load_feed_transfers, station_walk, min_change};
+import gtfs::osm::{load_osm, walkable};
+import gtfs::streets::{street_network, street_transfers};
+
+fn usage() {
+    io::println("usage: journey <gtfs dir> <from stop id> <to stop id> <YYYY-MM-DD> <HH:MM> [max transfers [osm file]]");
+    io::println("       with an OSM extract (.osm or .osm.pbf), walks follow the streets");
+}
+
+fn main(args: [str])
+{
+    if vec::len(args) < 6u || vec::len(args) > 8u {
+        usage();
+        ret;
+    }
+    let from = args[2], to = args[3];
+    let date = gtfs::parse_date(args[4]);
+    let depart = gtfs::gettime(args[5] + ":00");
+    let max_transfers = if vec::len(args) > 6u {
+        alt uint::from_str(args[6]) {
+            some(n) { n }
+            none { fail("invalid number of transfers") }
+        }
+    } else {
+        default_max_transfers
+    };
+
+    let feed = gtfs_load(args[1]);
+    let w = default_walking();
+    let transfers = if vec::len(args) == 8u {
+        street_transfers(feed, street_network(feed, load_osm(args[7], walkable)), w, some(station_walk))
+    } else {
+        walking_transfers(feed, w, some(station_walk))
+    };
+    merge_transfers(transfers, load_feed_transfers(feed, args[1], w));
+    let net = build_network(feed, date, transfers);
+    let its = plan(net, from, to, depart, max_transfers, min_change);
+    if vec::len(its) == 0u {
+        io::println("no journey found");
+        ret;
+    }
+    for vec::each(its) { |it|
+        io::println(#fmt("depart %s arrive %s, %u transfers",
+                gtfs::time_format(it.depart), gtfs::time_format(it.arrive), it.transfers));
+        for vec::each(it.legs) { |leg|
+            let what = alt leg.route_id {
+                some(id) { "route " + feed.routes.get(id).short_name }
+                none { "walk" }
+            };
+            io::println(#fmt("  %s %-20s %s -> %s %s",
+                    gtfs::time_format(leg.depart), what,
+                    feed.stops.get(leg.from_stop).name,
+                    gtfs::time_format(leg.arrive),
+                    feed.stops.get(leg.to_stop).name));
+        }
+    }
+}
@@ -0,0 +1,40 @@
+
+use gtfs;
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::gtfs_load_tables;
+import gtfs::{feedaccess};
+import gtfs::rng::{new_rng};
+import gtfs::demand::{zones_from_stops, zones_from_geojson, load_od, generate_requests,
+                      requests_csv};
+import gtfs::report::{write_file};
+
+fn usage() {
+    io::println("usage: mkdemand <gtfs dir> <zones geojson|stops> <od csv> <seed> <agents csv> [id property]");
+    io::println("       with 'stops', zones are the stops' zone_ids; otherwise each feature's id property (default zone_id)");
+    io::println("       od files have origin_zone, destination_zone, start_time, end_time and trips columns");
+    io::println("       the same seed gives the same agents");
+}
+
+fn main(args: [str])
+{
+    if vec::len(args) != 6u && vec::len(args) != 7u {
+        usage();
+        ret;
+    }
+    let seed = alt uint::from_str(args[4]) {
+        some(n) { n }
+        none { usage(); ret; }
+    };
+    let feed = gtfs_load_tables(args[1]);
+    let zones = if args[2] == "stops" {
+        zones_from_stops(feed)
+    } else {
+        zones_from_geojson(feed, args[2], if vec::len(args) == 7u { args[6] } else { "zone_id" })
+    };
+    let requests = generate_requests(zones, load_od(args[3]), new_rng(seed as u64));
+    io::println(#fmt("%u zones, %u agents", vec::len(zones), vec::len(requests)));
+    write_file(args[5], requests_csv(requests));
+}
@@ -0,0 +1,38 @@
+
+use gtfs;
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::gtfs_load;
+import gtfs::{feedaccess};
+import gtfs::osm::{load_osm};
+import gtfs::shapegen::{wanted_way, build_networks, generate_shapes, shapes_csv,
+                          trips_csv, stop_times_csv};
+import gtfs::report::{write_file};
+
+fn usage() {
+    io::println("usage: mkshapes <gtfs dir> <osm file> <output dir>");
+    io::println("       writes shapes.txt, and trips.txt and stop_times.txt with each trip's");
+    io::println("       shape_id and each stop's shape_dist_traveled");
+}
+
+fn main(args: [str])
+{
+    if vec::len(args) != 4u {
+        usage();
+        ret;
+    }
+    let feed = gtfs_load(args[1]);
+    let networks = build_networks(load_osm(args[2], wanted_way));
+    let mut trip_ids = [];
+    for feed.trips.each_value() { |trip|
+        if feed.stop_times.contains_key(trip.id) {
+            trip_ids += [ trip.id ];
+        }
+    }
+    let shapes = generate_shapes(feed, networks, trip_ids);
+    write_file(path::connect(args[3], "shapes.txt"), shapes_csv(shapes));
+    write_file(path::connect(args[3], "trips.txt"), trips_csv(feed, shapes));
+    write_file(path::connect(args[3], "stop_times.txt"), stop_times_csv(feed, shapes));
+}
@@ -0,0 +1,80 @@
+
+use gtfs;
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::gtfs_load;
+import gtfs::{feedaccess};
+import gtfs::raptor::{build_network, default_max_transfers};
+import gtfs::transfer::{walking_transfers, default_walking, merge_transfers,
+                        load_feed_transfers, station_walk, min_change};
+import gtfs::daysim::{day_trips};
+import gtfs::fleet::{blocks};
+import gtfs::rng::{new_rng};
+import gtfs::delay::{load_segment_spreads};
+import gtfs::passenger::{load_requests, plan_agent};
+import gtfs::crowding::{default_capacities, load_capacities};
+import gtfs::reliability::{monte_carlo, routes_csv, timepoints_csv, transfers_csv};
+import gtfs::report::{write_file};
+
+fn usage() {
+    io::println("usage: montecarlo <gtfs dir> <YYYY-MM-DD> <runs> <seed> <output dir> [segments csv|-");
+    io::println("       [agents csv [capacities csv]]]");
+    io::println("       segments have from_stop_id, to_stop_id, median and sigma columns");
+    io::println("       agents ride in every run, so vehicles stand at stops while they board;");
+    io::println("       they have agent_id, origin_stop_id, destination_stop_id and departure_time");
+    io::println("       capacities have a capacity column, and mode, route_id or trip_id");
+    io::println("       writes routes.csv, timepoints.csv and transfers.csv");
+    io::println("       the same seed gives the same results");
+}
+
+fn main(args: [str])
+{
+    let nargs = vec::len(args);
+    if nargs < 6u || nargs > 9u {
+        usage();
+        ret;
+    }
+    let (runs, seed) = alt (uint::from_str(args[3]), uint::from_str(args[4])) {
+        (some(n), some(s)) if n > 0u { (n, s) }
+        _ { usage(); ret; }
+    };
+    let feed = gtfs_load(args[1]);
+    let date = gtfs::parse_date(args[2]);
+    let service_ids = feed.active_service_ids(gtfs::weekday_of(date), date);
+    let trip_ids = vec::filter(feed.trip_ids_for_service_ids(service_ids)) { |id|
+        feed.stop_times.contains_key(id)
+    };
+    let segments = if nargs > 6u && args[6] != "-" {
+        load_segment_spreads(args[6])
+    } else {
+        map::str_hash()
+    };
+    let capacities = if nargs > 8u { load_capacities(args[8]) } else { default_capacities() };
+    let mut agents = [];
+    let mut unplanned = 0u;
+    if nargs > 7u {
+        let transfers = walking_transfers(feed, default_walking(), some(station_walk));
+        merge_transfers(transfers, load_feed_transfers(feed, args[1], default_walking()));
+        let net = build_network(feed, date, transfers);
+        for vec::each(load_requests(args[7])) { |r|
+            alt plan_agent(net, r, default_max_transfers, min_change) {
+                some(a) { agents += [ a ]; }
+                none { unplanned += 1u; }
+            }
+        }
+    }
+
+    let rel = monte_carlo(feed, day_trips(feed, trip_ids), blocks(feed, trip_ids), runs,
+                          new_rng(seed as u64), segments, agents, capacities, min_change);
+    io::println(#fmt("%u trips, %u runs", vec::len(trip_ids), runs));
+    if nargs > 7u {
+        io::println(#fmt("per run: %.1f arrived, %.1f stranded; %u with no journey",
+                         (rel.arrived as float) / (runs as float),
+                         (rel.stranded as float) / (runs as float), unplanned));
+    }
+    write_file(path::connect(args[5], "routes.csv"), routes_csv(rel));
+    write_file(path::connect(args[5], "timepoints.csv"), timepoints_csv(rel));
+    write_file(path::connect(args[5], "transfers.csv"), transfers_csv(rel));
+}
@@ -0,0 +1,166 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import std::sort;
+import gtfs::{feed, feedaccess, stop, point, file_iter, default_workers};
+import csa::{timetable, stop_lookup, earliest_arrivals};
+import transfer::{walking, walk_secs};
+import geom::{haversine};
+import report::{csv_row};
+
+export zone, load_zones, od_matrix, matrix_csv;
+
+/*
+ * travel times between many origins and destinations. leaving on a
+ * particular minute can be lucky or unlucky, so each pair is sampled at
+ * every minute of a window and summarised by percentiles. origins are
+ * shared out between tasks.
+ */
+
+type zone = {
+    id: str,
+    pt: point
+};
+
+/* zones from a CSV file with zone_id, lat and lon columns */
+fn load_zones(path: str) -> [ zone ] {
+    enum req { id, lat, lon }
+    let reqf = [
+        (id as uint, "zone_id"),
+        (lat as uint, "lat"),
+        (lon as uint, "lon")
+            ];
+    let num = { |s: str|
+        alt float::from_str(s) {
+            some(f) { f }
+            none { fail(#fmt("%s: not a number: %s", path, s)) }
+        }
+    };
+    let mut res = [];
+    file_iter(path, reqf, []) { |row, req, _opt|
+        res += [ {
+            id: row[req[id as uint]],
+            pt: { lat: num(row[req[lat as uint]]), lon: num(row[req[lon as uint]]) }
+        } ];
+    };
+    ret res;
+}
+
+/* the timetable's stops in walking distance of a point, with the walk in
+   seconds */
+fn walks_near(stops: map::hashmap<str, uint>, walks: [ (@stop, uint) ]) -> [ (uint, uint) ] {
+    let mut res = [];
+    for vec::each(walks) { |walk|
+        let (stop, secs) = walk;
+        alt stops.find(stop.id) {
+            some(s) { res += [ (s, secs) ]; }
+            none {}
+        }
+    }
+    ret res;
+}
+
+/* the p'th percentile of some travel times, by nearest rank; with no
+   times at all, uint::max_value, as for out of reach */
+fn percentile(sorted: [ uint ], p: uint) -> uint {
+    let n = vec::len(sorted);
+    if n == 0u {
+        ret uint::max_value;
+    }
+    let rank = (p * n + 99u) / 100u;
+    sorted[if rank == 0u { 0u } else { rank - 1u }]
+}
+
+/* travel times in seconds from one origin to every destination, for each
+   departure minute in [start, end) */
+fn sample_origin(tt: timetable, access: [ (uint, uint) ], egress: [ [ (uint, uint) ] ],
+                 direct: [ option<uint> ], start: uint, end: uint, max_time: uint,
+                 change: uint) -> [ [ uint ] ] {
+    let never = uint::max_value;
+    let ndest = vec::len(egress);
+    let mut samples = vec::to_mut(vec::from_elem(ndest, []));
+    let mut depart = start;
+    while depart < end {
+        let sources = vec::map(access) { |a| let (s, secs) = a; (s, depart + secs) };
+        let arrivals = earliest_arrivals(tt, sources, depart + max_time, change);
+        for uint::range(0u, ndest) { |d|
+            let mut best = alt direct[d] {
+                some(secs) { secs }
+                none { never }
+            };
+            for vec::each(egress[d]) { |e|
+                let (s, secs) = e;
+                if arrivals[s] != never {
+                    best = uint::min(best, arrivals[s] + secs - depart);
+                }
+            }
+            samples[d] += [ if best <= max_time { best } else { never } ];
+        }
+        depart += 60u;
+    }
+    ret vec::from_mut(samples);
+}
+
+/* for every origin and destination, the given percentiles of travel time
+   over departures each minute in [start, end): result[o][d][i] is the
+   percentile[i], in seconds, or uint::max_value if it takes longer than
+   `max_time`. `walks` gives the stops in reach of a point; `w` is used
+   for walking straight from origin to destination */
+fn od_matrix(feed: feed, tt: timetable, origins: [ zone ], destinations: [ zone ],
+             start: uint, end: uint, max_time: uint, walks: fn@(point) -> [ (@stop, uint) ],
+             w: walking, change: uint, percentiles: [ uint ]) -> [ [ [ uint ] ] ] {
+    let stops = stop_lookup(tt);
+    let access = vec::map(origins) { |z| walks_near(stops, walks(z.pt)) };
+    let egress = vec::map(destinations) { |z| walks_near(stops, walks(z.pt)) };
+    let direct = vec::map(origins) { |o|
+        vec::map(destinations) { |d|
+            let metres = haversine(o.pt, d.pt);
+            if metres <= w.radius { some(walk_secs(w, metres)) } else { none }
+        }
+    };
+
+    /* each worker takes every nworkers'th origin, and sends back rows */
+    let nworkers = uint::max(1u, uint::min(default_workers(), vec::len(origins)));
+    let p = comm::port::<(uint, [ [ uint ] ])>();
+    let ch = comm::chan(p);
+    for uint::range(0u, nworkers) { |wk|
+        task::spawn { ||
+            let mut o = wk;
+            while o < vec::len(access) {
+                let samples = sample_origin(tt, access[o], egress, direct[o], start, end, max_time, change);
+                let row = vec::map(samples) { |v|
+                    let sorted = sort::merge_sort({|a, b| a <= b }, v);
+                    vec::map(percentiles) { |pc| percentile(sorted, pc) }
+                };
+                comm::send(ch, (o, row));
+                o += nworkers;
+            }
+        };
+    }
+    let mut rows = vec::to_mut(vec::from_elem(vec::len(origins), []));
+    for uint::range(0u, vec::len(origins)) { |_i|
+        let (o, row) = comm::recv(p);
+        rows[o] = row;
+    }
+    ret vec::from_mut(rows);
+}
+
+/* the matrix, in minutes: a column for each destination, and for each
+   origin a row for each percentile. cells out of reach are left empty */
+fn matrix_csv(origins: [ zone ], destinations: [ zone ], percentiles: [ uint ],
+              matrix: [ [ [ uint ] ] ]) -> str {
+    let never = uint::max_value;
+    let header = [ "origin", "percentile" ] + vec::map(destinations) { |z| z.id };
+    let mut res = csv_row(header) + "\n";
+    for vec::eachi(matrix) { |o, row|
+        for vec::eachi(percentiles) { |p, pc|
+            let cells = vec::map(row) { |stats|
+                let v = if p < vec::len(stats) { stats[p] } else { never };
+                if v == never { "" } else { #fmt("%.1f", (v as float) / 60.) }
+            };
+            res += csv_row([ origins[o].id, #fmt("p%u", pc) ] + cells) + "\n";
+        }
+    }
+    ret res;
+}
@@ -0,0 +1,59 @@
+
+use gtfs;
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::gtfs_load;
+import gtfs::{feedaccess};
+import gtfs::csa::{build_timetable};
+import gtfs::transfer::{walking_transfers, default_walking, crow_walks, merge_transfers,
+                        load_feed_transfers, station_walk, min_change};
+import gtfs::osm::{load_osm, walkable};
+import gtfs::streets::{street_network, street_walks, street_transfers};
+import gtfs::odmatrix::{load_zones, od_matrix, matrix_csv};
+import gtfs::report::{write_file};
+
+/* journeys longer than this count as unreachable */
+const max_time : uint = 7200u;
+
+fn usage() {
+    io::println("usage: odtimes <gtfs dir> <origins csv> <destinations csv> <YYYY-MM-DD> <HH:MM> <HH:MM> <output csv> [osm file]");
+    io::println("       zone files have zone_id, lat and lon columns");
+    io::println("       the output has a column of minutes for each destination, and a row for");
+    io::println("       each origin at each of the 10th, 25th, 50th, 75th and 90th percentiles");
+    io::println("       with an OSM extract (.osm or .osm.pbf), walks follow the streets");
+}
+
+fn main(args: [str])
+{
+    if vec::len(args) != 8u && vec::len(args) != 9u {
+        usage();
+        ret;
+    }
+    let origins = load_zones(args[2]);
+    let destinations = load_zones(args[3]);
+    let date = gtfs::parse_date(args[4]);
+    let start = gtfs::gettime(args[5] + ":00");
+    let end = gtfs::gettime(args[6] + ":00");
+    if end <= start {
+        io::println("the window must end after it starts");
+        usage();
+        ret;
+    }
+    let percentiles = [ 10u, 25u, 50u, 75u, 90u ];
+
+    let feed = gtfs_load(args[1]);
+    let w = default_walking();
+    let (transfers, walks) = if vec::len(args) == 9u {
+        let s = street_network(feed, load_osm(args[8], walkable));
+        (street_transfers(feed, s, w, some(station_walk)), { |pt| street_walks(feed, s, pt, w) })
+    } else {
+        (walking_transfers(feed, w, some(station_walk)), { |pt| crow_walks(feed, w, pt) })
+    };
+    merge_transfers(transfers, load_feed_transfers(feed, args[1], w));
+    let tt = build_timetable(feed, date, transfers);
+    let matrix = od_matrix(feed, tt, origins, destinations, start, end, max_time,
+                           walks, w, min_change, percentiles);
+    write_file(args[7], matrix_csv(origins, destinations, percentiles, matrix));
+}
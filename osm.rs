@@ -0,0 +1,465 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::{point};
+import inflate::{zlib_inflate};
+
+export osm, way, load_osm, tag_value, walkable, drivable, rail_track, tram_track;
+
+/*
+ * reads OpenStreetMap extracts, as XML (.osm) or protobuf (.osm.pbf). only
+ * node positions and the ways the caller wants are kept; relations and
+ * metadata are skipped.
+ */
+
+type way = {
+    id: int,
+    refs: [ int ],
+    tags: [ (str, str) ]
+};
+
+type osm = {
+    nodes: map::hashmap<int, point>,
+    ways: [ way ]
+};
+
+/* picks the ways worth keeping, from their tags */
+type way_filter = fn@([ (str, str) ]) -> bool;
+
+fn tag_value(tags: [ (str, str) ], key: str) -> option<str> {
+    for vec::each(tags) { |kv|
+        let (k, v) = kv;
+        if k == key {
+            ret some(v);
+        }
+    }
+    ret none;
+}
+
+/* ways someone can walk along */
+fn walkable(tags: [ (str, str) ]) -> bool {
+    let is = { |key: str, values: [ str ]|
+        alt tag_value(tags, key) {
+            some(v) { vec::contains(values, v) }
+            none { false }
+        }
+    };
+    if is("foot", [ "no", "private" ]) {
+        ret false;
+    }
+    if is("access", [ "no", "private" ]) && !is("foot", [ "yes", "designated", "permissive" ]) {
+        ret false;
+    }
+    if is("foot", [ "yes", "designated", "permissive" ]) {
+        ret true;
+    }
+    is("highway", [ "footway", "pedestrian", "path", "steps", "living_street",
+                    "residential", "service", "unclassified", "road", "track",
+                    "cycleway", "corridor", "platform", "tertiary", "tertiary_link",
+                    "secondary", "secondary_link", "primary", "primary_link" ])
+        || is("railway", [ "platform" ])
+        || is("public_transport", [ "platform" ])
+}
+
+/* ways a bus can use */
+fn drivable(tags: [ (str, str) ]) -> bool {
+    alt tag_value(tags, "highway") {
+        some(h) {
+            vec::contains([ "motorway", "motorway_link", "trunk", "trunk_link",
+                            "primary", "primary_link", "secondary", "secondary_link",
+                            "tertiary", "tertiary_link", "unclassified", "residential",
+                            "living_street", "service", "road", "busway", "bus_guideway" ], h)
+                || (h == "pedestrian" && tag_value(tags, "bus") == some("yes"))
+        }
+        none { false }
+    }
+}
+
+/* heavy rail, metro and light rail track */
+fn rail_track(tags: [ (str, str) ]) -> bool {
+    alt tag_value(tags, "railway") {
+        some(r) { vec::contains([ "rail", "light_rail", "subway", "narrow_gauge", "funicular" ], r) }
+        none { false }
+    }
+}
+
+/* tram track; light rail often shares it */
+fn tram_track(tags: [ (str, str) ]) -> bool {
+    alt tag_value(tags, "railway") {
+        some(r) { r == "tram" || r == "light_rail" }
+        none { false }
+    }
+}
+
+fn load_osm(path: str, want: way_filter) -> osm {
+    io::println("loading file: " + path);
+    if str::ends_with(path, ".pbf") {
+        load_pbf(path, want)
+    } else {
+        load_xml(path, want)
+    }
+}
+
+fn open(path: str) -> io::reader {
+    alt io::file_reader(path) {
+        result::ok(r) { r }
+        result::err(e) { fail(#fmt("cannot open %s: %s", path, e)) }
+    }
+}
+
+fn get_int(path: str, s: str) -> int {
+    alt int::from_str(s) {
+        some(v) { v }
+        none { fail(#fmt("%s: bad id: %s", path, s)) }
+    }
+}
+
+fn get_float(path: str, s: str) -> float {
+    alt float::from_str(s) {
+        some(v) { v }
+        none { fail(#fmt("%s: bad coordinate: %s", path, s)) }
+    }
+}
+
+/* xml */
+
+fn unescape(s: str) -> str {
+    if !str::contains(s, "&") {
+        ret s;
+    }
+    let s = str::replace(s, "&lt;", "<");
+    let s = str::replace(s, "&gt;", ">");
+    let s = str::replace(s, "&quot;", "\"");
+    let s = str::replace(s, "&apos;", "'");
+    str::replace(s, "&amp;", "&")
+}
+
+fn is_space(b: u8) -> bool {
+    b == ' ' as u8 || b == '\t' as u8 || b == '\n' as u8 || b == '\r' as u8
+}
+
+/* the element name and attributes from the text between < and > */
+fn parse_tag(t: str) -> (str, [ (str, str) ]) {
+    let n = str::len(t);
+    let mut i = 0u;
+    while i < n && !is_space(t[i]) && t[i] != '/' as u8 {
+        i += 1u;
+    }
+    let name = str::slice(t, 0u, i);
+    let mut attrs = [];
+    loop {
+        while i < n && (is_space(t[i]) || t[i] == '/' as u8) {
+            i += 1u;
+        }
+        let kstart = i;
+        while i < n && t[i] != '=' as u8 && !is_space(t[i]) {
+            i += 1u;
+        }
+        let key = str::slice(t, kstart, i);
+        while i < n && t[i] != '"' as u8 && t[i] != '\'' as u8 {
+            i += 1u;
+        }
+        if i >= n {
+            break;
+        }
+        let quote = t[i];
+        let vstart = i + 1u;
+        i = vstart;
+        while i < n && t[i] != quote {
+            i += 1u;
+        }
+        attrs += [ (key, unescape(str::slice(t, vstart, uint::min(i, n)))) ];
+        i += 1u;
+    }
+    ret (name, attrs);
+}
+
+/* calls `f` with each element's name and attributes, whether it closes
+   an element, and whether it's empty (<x/>) */
+fn each_element(path: str, f: fn(str, [ (str, str) ], bool, bool)) {
+    let reader = open(path);
+    let mut buf = "";
+    while !reader.eof() {
+        buf += reader.read_line() + "\n";
+        loop {
+            let start = alt str::find_char(buf, '<') {
+                some(p) { p }
+                none { buf = ""; break; }
+            };
+            let end = alt str::find_char_from(buf, '>', start) {
+                some(p) { p }
+                none { buf = str::slice(buf, start, str::len(buf)); break; }
+            };
+            let inner = str::slice(buf, start + 1u, end);
+            buf = str::slice(buf, end + 1u, str::len(buf));
+            if str::len(inner) == 0u || inner[0] == '?' as u8 || inner[0] == '!' as u8 {
+                cont;
+            }
+            if inner[0] == '/' as u8 {
+                f(str::slice(inner, 1u, str::len(inner)), [], true, false);
+            } else {
+                let (name, attrs) = parse_tag(inner);
+                f(name, attrs, false, str::ends_with(inner, "/"));
+            }
+        }
+    }
+}
+
+fn load_xml(path: str, want: way_filter) -> osm {
+    let nodes = map::int_hash();
+    let mut ways = [];
+    let mut in_way = false;
+    let mut way_id = 0;
+    let mut refs = [];
+    let mut tags = [];
+    let attr = { |attrs: [ (str, str) ], key: str|
+        alt tag_value(attrs, key) {
+            some(v) { v }
+            none { fail(#fmt("%s: element without %s", path, key)) }
+        }
+    };
+    each_element(path) { |name, attrs, closing, empty|
+        if closing {
+            if name == "way" && in_way {
+                if want(tags) {
+                    ways += [ { id: way_id, refs: refs, tags: tags } ];
+                }
+                in_way = false;
+            }
+        } else if name == "node" {
+            let id = get_int(path, attr(attrs, "id"));
+            nodes.insert(id, { lat: get_float(path, attr(attrs, "lat")),
+                               lon: get_float(path, attr(attrs, "lon")) });
+        } else if name == "way" {
+            in_way = !empty;
+            way_id = get_int(path, attr(attrs, "id"));
+            refs = [];
+            tags = [];
+        } else if name == "nd" && in_way {
+            refs += [ get_int(path, attr(attrs, "ref")) ];
+        } else if name == "tag" && in_way {
+            tags += [ (attr(attrs, "k"), attr(attrs, "v")) ];
+        }
+    };
+    ret { nodes: nodes, ways: ways };
+}
+
+/* pbf */
+
+enum pb_field {
+    pb_int(u64),
+    pb_bytes([ u8 ])
+}
+
+type cursor = @{ buf: [ u8 ], mut pos: uint };
+
+fn varint(c: cursor) -> u64 {
+    let mut res = 0u64;
+    let mut shift = 0u64;
+    loop {
+        if c.pos >= vec::len(c.buf) {
+            fail("osm: truncated protobuf varint");
+        }
+        let b = c.buf[c.pos];
+        c.pos += 1u;
+        res |= ((b & 0x7fu8) as u64) << shift;
+        if b & 0x80u8 == 0u8 {
+            ret res;
+        }
+        shift += 7u64;
+    }
+}
+
+fn zigzag(v: u64) -> i64 {
+    ((v >> 1u64) as i64) ^ -((v & 1u64) as i64)
+}
+
+/* calls `f` with the number and value of each field of a message; fixed
+   width fields aren't used by the OSM format and are skipped */
+fn each_field(buf: [ u8 ], f: fn(uint, pb_field)) {
+    let c = @{ buf: buf, mut pos: 0u };
+    while c.pos < vec::len(buf) {
+        let key = varint(c) as uint;
+        alt key & 7u {
+            0u { f(key >> 3u, pb_int(varint(c))); }
+            1u { c.pos += 8u; }
+            2u {
+                let n = varint(c) as uint;
+                if c.pos + n > vec::len(buf) {
+                    fail("osm: truncated protobuf field");
+                }
+                f(key >> 3u, pb_bytes(vec::slice(buf, c.pos, c.pos + n)));
+                c.pos += n;
+            }
+            5u { c.pos += 4u; }
+            _ { fail(#fmt("osm: unsupported protobuf wire type %u", key & 7u)); }
+        }
+    }
+}
+
+fn packed(buf: [ u8 ]) -> [ u64 ] {
+    let c = @{ buf: buf, mut pos: 0u };
+    let mut res = [];
+    while c.pos < vec::len(buf) {
+        res += [ varint(c) ];
+    }
+    ret res;
+}
+
+/* undoes the delta coding of packed sint64s */
+fn packed_deltas(buf: [ u8 ]) -> [ i64 ] {
+    let mut last = 0i64;
+    vec::map(packed(buf)) { |v|
+        last += zigzag(v);
+        last
+    }
+}
+
+fn be32(b: [ u8 ]) -> uint {
+    ((b[0] as uint) << 24u) | ((b[1] as uint) << 16u) | ((b[2] as uint) << 8u) | (b[3] as uint)
+}
+
+fn blob_data(blob: [ u8 ]) -> [ u8 ] {
+    let mut res = none;
+    each_field(blob) { |n, f|
+        alt (n, f) {
+            (1u, pb_bytes(raw)) { res = some(raw); }
+            (3u, pb_bytes(z)) { res = some(zlib_inflate(z)); }
+            (4u, _) { fail("osm: lzma compressed blocks aren't supported"); }
+            _ {}
+        }
+    };
+    alt res {
+        some(data) { data }
+        none { fail("osm: empty blob") }
+    }
+}
+
+/* nanodegrees, as the block stores them, to degrees */
+fn degrees_of(offset: i64, granularity: i64, v: i64) -> float {
+    ((offset + granularity * v) as float) / 1e9
+}
+
+fn primitive_block(data: [ u8 ], nodes: map::hashmap<int, point>, want: way_filter) -> [ way ] {
+    let mut strings = [];
+    let mut groups = [];
+    let mut granularity = 100i64;
+    let mut lat_offset = 0i64;
+    let mut lon_offset = 0i64;
+    each_field(data) { |n, f|
+        alt (n, f) {
+            (1u, pb_bytes(table)) {
+                each_field(table) { |n, f|
+                    alt (n, f) {
+                        (1u, pb_bytes(s)) { strings += [ str::from_bytes(s) ]; }
+                        _ {}
+                    }
+                };
+            }
+            (2u, pb_bytes(g)) { groups += [ g ]; }
+            (17u, pb_int(v)) { granularity = v as i64; }
+            (19u, pb_int(v)) { lat_offset = v as i64; }
+            (20u, pb_int(v)) { lon_offset = v as i64; }
+            _ {}
+        }
+    };
+
+    let mut ways = [];
+    for vec::each(groups) { |g|
+        each_field(g) { |n, f|
+            alt (n, f) {
+                (1u, pb_bytes(node)) {
+                    let mut id = 0i64;
+                    let mut lat = 0i64;
+                    let mut lon = 0i64;
+                    each_field(node) { |n, f|
+                        alt (n, f) {
+                            (1u, pb_int(v)) { id = zigzag(v); }
+                            (8u, pb_int(v)) { lat = zigzag(v); }
+                            (9u, pb_int(v)) { lon = zigzag(v); }
+                            _ {}
+                        }
+                    };
+                    nodes.insert(id as int, { lat: degrees_of(lat_offset, granularity, lat),
+                                              lon: degrees_of(lon_offset, granularity, lon) });
+                }
+                (2u, pb_bytes(dense)) {
+                    let mut ids = [];
+                    let mut lats = [];
+                    let mut lons = [];
+                    each_field(dense) { |n, f|
+                        alt (n, f) {
+                            (1u, pb_bytes(b)) { ids = packed_deltas(b); }
+                            (8u, pb_bytes(b)) { lats = packed_deltas(b); }
+                            (9u, pb_bytes(b)) { lons = packed_deltas(b); }
+                            _ {}
+                        }
+                    };
+                    if vec::len(lats) != vec::len(ids) || vec::len(lons) != vec::len(ids) {
+                        fail("osm: dense nodes with mismatched arrays");
+                    }
+                    for vec::eachi(ids) { |i, id|
+                        nodes.insert(id as int, { lat: degrees_of(lat_offset, granularity, lats[i]),
+                                                  lon: degrees_of(lon_offset, granularity, lons[i]) });
+                    }
+                }
+                (3u, pb_bytes(w)) {
+                    let mut id = 0;
+                    let mut keys = [];
+                    let mut vals = [];
+                    let mut refs = [];
+                    each_field(w) { |n, f|
+                        alt (n, f) {
+                            (1u, pb_int(v)) { id = v as int; }
+                            (2u, pb_bytes(b)) { keys = packed(b); }
+                            (3u, pb_bytes(b)) { vals = packed(b); }
+                            (8u, pb_bytes(b)) { refs = vec::map(packed_deltas(b)) { |r| r as int }; }
+                            _ {}
+                        }
+                    };
+                    let tags = vec::map2(keys, vals) { |k, v|
+                        (strings[k as uint], strings[v as uint])
+                    };
+                    if want(tags) {
+                        ways += [ { id: id, refs: refs, tags: tags } ];
+                    }
+                }
+                _ {}
+            }
+        };
+    }
+    ret ways;
+}
+
+fn load_pbf(path: str, want: way_filter) -> osm {
+    let reader = open(path);
+    let nodes = map::int_hash();
+    let mut ways = [];
+    while !reader.eof() {
+        let len = reader.read_bytes(4u);
+        if vec::len(len) < 4u {
+            break;
+        }
+        let header = reader.read_bytes(be32(len));
+        let mut kind = "";
+        let mut size = 0u;
+        each_field(header) { |n, f|
+            alt (n, f) {
+                (1u, pb_bytes(b)) { kind = str::from_bytes(b); }
+                (3u, pb_int(v)) { size = v as uint; }
+                _ {}
+            }
+        };
+        let blob = reader.read_bytes(size);
+        if vec::len(blob) != size {
+            fail(#fmt("%s: truncated block", path));
+        }
+        alt kind {
+            "OSMHeader" {}
+            "OSMData" { ways += primitive_block(blob_data(blob), nodes, want); }
+            _ {}
+        }
+    }
+    ret { nodes: nodes, ways: ways };
+}
@@ -0,0 +1,367 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::{feed, feedaccess, trip, stop_time, marshal, file_iter, gettime, time_format};
+import raptor::{network, leg, earliest_arrival};
+import daysim::{observer};
+import positions::{fleet};
+import pqueue::{pqueue, new_pqueue, push, pop, peek_key};
+import crowding::{capacities, capacity_of, stop_load};
+import report::{csv_row};
+
+export request, load_requests, agent, plan_agent, fixed_agent,
+       passengers, new_passengers, add_agent, finish, outcomes, stop_loads,
+       journey_log_csv;
+
+/*
+ * people riding the simulated vehicles. each agent has an itinerary, from
+ * the router or given, and follows it leg by leg: walking, waiting at the
+ * stop, boarding when a suitable vehicle arrives and getting off at the
+ * leg's last stop. a ride leg can be taken on any trip of its route which
+ * goes on to that stop, not just the one planned, so an agent who misses a
+ * trip takes the next. vehicles fill up: anyone who can't get on is left
+ * behind to wait for the next.
+ */
+
+/* someone wanting to get from one stop to another */
+type request = {
+    id: str,
+    origin: str,
+    destination: str,
+    /* when they'd like to leave */
+    depart: uint
+};
+
+type agent = {
+    id: str,
+    origin: str,
+    destination: str,
+    depart: uint,
+    legs: [ leg ]
+};
+
+/* requests from a CSV file with agent_id, origin_stop_id,
+   destination_stop_id and departure_time columns */
+fn load_requests(path: str) -> [ request ] {
+    enum req { id, origin, destination, depart }
+    let reqf = [
+        (id as uint, "agent_id"),
+        (origin as uint, "origin_stop_id"),
+        (destination as uint, "destination_stop_id"),
+        (depart as uint, "departure_time")
+            ];
+    let mut res = [];
+    file_iter(path, reqf, []) { |row, req, _opt|
+        res += [ {
+            id: row[req[id as uint]],
+            origin: row[req[origin as uint]],
+            destination: row[req[destination as uint]],
+            depart: gettime(row[req[depart as uint]])
+        } ];
+    };
+    ret res;
+}
+
+/* an agent following the given itinerary */
+fn fixed_agent(r: request, legs: [ leg ]) -> agent {
+    { id: r.id, origin: r.origin, destination: r.destination, depart: r.depart, legs: legs }
+}
+
+/* an agent taking the earliest arriving journey, if there is one */
+fn plan_agent(net: network, r: request, max_transfers: uint, change: uint) -> option<agent> {
+    alt earliest_arrival(net, r.origin, r.destination, r.depart, max_transfers, change) {
+        some(it) { some(fixed_agent(r, it.legs)) }
+        none { none }
+    }
+}
+
+type journey_event = {
+    time: uint,
+    what: str,
+    stop_id: str,
+    trip_id: option<str>
+};
+
+enum status {
+    not_started,
+    on_foot,
+    at_stop,
+    aboard,
+    done,
+    stuck
+}
+
+type rider = @{
+    agent: agent,
+    /* the leg under way */
+    mut leg: uint,
+    mut status: status,
+    mut log: [ journey_event ]
+};
+
+type passengers = @{
+    feed: feed,
+    capacities: capacities,
+    mut riders: [ rider ],
+    /* riders to start, or to finish walking, by when */
+    due: pqueue<uint>,
+    /* riders waiting at each stop */
+    waiting: map::hashmap<str, [ uint ]>,
+    /* riders on each trip */
+    riding: map::hashmap<str, [ uint ]>,
+    /* one for each stop vehicles called at */
+    mut loads: [ stop_load ],
+    mut now: uint
+};
+
+fn new_passengers(feed: feed, capacities: capacities) -> passengers {
+    @{
+        feed: feed,
+        capacities: capacities,
+        mut riders: [],
+        due: new_pqueue(),
+        waiting: map::str_hash(),
+        riding: map::str_hash(),
+        mut loads: [],
+        mut now: 0u
+    }
+}
+
+fn add_agent(p: passengers, a: agent) {
+    push(p.due, a.depart, vec::len(p.riders));
+    p.riders += [ @{ agent: a, mut leg: 0u, mut status: not_started, mut log: [] } ];
+}
+
+fn note(r: rider, time: uint, what: str, stop_id: str, trip_id: option<str>) {
+    r.log += [ { time: time, what: what, stop_id: stop_id, trip_id: trip_id } ];
+}
+
+fn add_to(m: map::hashmap<str, [ uint ]>, key: str, i: uint) {
+    let v = alt m.find(key) {
+        some(v) { v }
+        none { [] }
+    };
+    m.insert(key, v + [ i ]);
+}
+
+fn remove_from(m: map::hashmap<str, [ uint ]>, key: str, gone: [ uint ]) {
+    alt m.find(key) {
+        some(v) { m.insert(key, vec::filter(v) { |i| !vec::contains(gone, i) }); }
+        none {}
+    }
+}
+
+/* sets rider `i` off on its current leg, or has it arrive if there are
+   none left */
+fn begin_leg(p: passengers, i: uint, now: uint) {
+    let r = p.riders[i];
+    if r.leg == vec::len(r.agent.legs) {
+        r.status = done;
+        note(r, now, "arrive", r.agent.destination, none);
+        ret;
+    }
+    let l = r.agent.legs[r.leg];
+    alt l.trip_id {
+        none {
+            r.status = on_foot;
+            note(r, now, "walk", l.from_stop, none);
+            push(p.due, now + (l.arrive - l.depart), i);
+        }
+        some(_) {
+            r.status = at_stop;
+            note(r, now, "wait", l.from_stop, none);
+            add_to(p.waiting, l.from_stop, i);
+        }
+    }
+}
+
+/* starts and finishes the walks of everyone due by `now` */
+fn catch_up(p: passengers, now: uint) {
+    loop {
+        alt peek_key(p.due) {
+            some(t) if t <= now {}
+            _ { break; }
+        }
+        let (t, i) = option::get(pop(p.due));
+        let r = p.riders[i];
+        alt r.status {
+            not_started {
+                note(r, t, "start", r.agent.origin, none);
+                begin_leg(p, i, t);
+            }
+            on_foot {
+                r.leg += 1u;
+                begin_leg(p, i, t);
+            }
+            _ {}
+        }
+    }
+    p.now = uint::max(p.now, now);
+}
+
+/* whether a rider on leg `l` would get on `trip` at `st`: it must be the
+   leg's route, allow pickup here, and go on to the leg's last stop and
+   allow drop off there */
+fn accepts(p: passengers, l: leg, trip: @trip, st: @stop_time) -> bool {
+    if l.trip_id != some(trip.id) && l.route_id != some(trip.route_id) {
+        ret false;
+    }
+    if !marshal_allowed(st.pickup_type) {
+        ret false;
+    }
+    for vec::each(p.feed.stop_times.get(trip.id)) { |later|
+        if later.sequence > st.sequence && later.stop_id == l.to_stop {
+            ret marshal_allowed(later.drop_off_type);
+        }
+    }
+    ret false;
+}
+
+impl of observer for passengers {
+    fn trip_started(now: uint, _trip: @trip) {
+        catch_up(self, now);
+    }
+
+    fn arrived(now: uint, trip: @trip, st: @stop_time) -> (uint, uint) {
+        catch_up(self, now);
+        /* off first, then on */
+        let mut off = [];
+        for vec::each(alt self.riding.find(trip.id) { some(v) { v } none { [] } }) { |i|
+            let r = self.riders[i];
+            if r.agent.legs[r.leg].to_stop == st.stop_id && marshal_allowed(st.drop_off_type) {
+                off += [ i ];
+            }
+        }
+        remove_from(self.riding, trip.id, off);
+        for vec::each(off) { |i|
+            let r = self.riders[i];
+            note(r, now, "alight", st.stop_id, some(trip.id));
+            r.leg += 1u;
+            begin_leg(self, i, now);
+        }
+
+        /* in the order they started waiting, while there's room */
+        let capacity = capacity_of(self.capacities, self.feed, trip);
+        let mut load = alt self.riding.find(trip.id) {
+            some(v) { vec::len(v) }
+            none { 0u }
+        };
+        let mut on = [];
+        let mut denied = 0u;
+        for vec::each(alt self.waiting.find(st.stop_id) { some(v) { v } none { [] } }) { |i|
+            let r = self.riders[i];
+            if r.status == at_stop && accepts(self, r.agent.legs[r.leg], trip, st) {
+                if load < capacity {
+                    on += [ i ];
+                    load += 1u;
+                } else {
+                    note(r, now, "denied", st.stop_id, some(trip.id));
+                    denied += 1u;
+                }
+            }
+        }
+        remove_from(self.waiting, st.stop_id, on);
+        for vec::each(on) { |i|
+            let r = self.riders[i];
+            r.status = aboard;
+            note(r, now, "board", st.stop_id, some(trip.id));
+            add_to(self.riding, trip.id, i);
+        }
+        self.loads += [ {
+            trip_id: trip.id,
+            route_id: trip.route_id,
+            stop_id: st.stop_id,
+            sequence: st.sequence,
+            time: now,
+            boarded: vec::len(on),
+            alighted: vec::len(off),
+            denied: denied,
+            load: load,
+            capacity: capacity
+        } ];
+        ret (vec::len(on), vec::len(off));
+    }
+
+    fn departed(now: uint, _trip: @trip, _st: @stop_time, _dwell: uint) {
+        catch_up(self, now);
+    }
+
+    /* anyone still aboard never got to their stop */
+    fn trip_ended(now: uint, trip: @trip) {
+        catch_up(self, now);
+        alt self.riding.find(trip.id) {
+            some(v) {
+                let times = self.feed.stop_times.get(trip.id);
+                let last = times[vec::len(times) - 1u];
+                for vec::each(v) { |i|
+                    let r = self.riders[i];
+                    r.status = stuck;
+                    note(r, now, "stranded", last.stop_id, some(trip.id));
+                }
+                self.riding.remove(trip.id);
+            }
+            none {}
+        }
+    }
+
+    fn layover(now: uint, _block_id: str, _trip: @trip, _next: @trip, _secs: uint) {
+        catch_up(self, now);
+    }
+
+    fn snapshot(now: uint, _running: fleet) {
+        catch_up(self, now);
+    }
+}
+
+/* once the day's run is over, those who haven't arrived are stranded */
+fn finish(p: passengers) {
+    let end = p.now;
+    catch_up(p, uint::max_value);
+    p.now = end;
+    for vec::each(p.riders) { |r|
+        alt r.status {
+            done | stuck {}
+            _ {
+                let stop_id = if r.leg < vec::len(r.agent.legs) {
+                    r.agent.legs[r.leg].from_stop
+                } else {
+                    r.agent.origin
+                };
+                r.status = stuck;
+                note(r, p.now, "stranded", stop_id, none);
+            }
+        }
+    }
+}
+
+/* how many agents arrived, and how many didn't */
+fn outcomes(p: passengers) -> (uint, uint) {
+    let mut arrived = 0u;
+    for vec::each(p.riders) { |r|
+        if r.status == done {
+            arrived += 1u;
+        }
+    }
+    ret (arrived, vec::len(p.riders) - arrived);
+}
+
+/* the load at every stop vehicles called at, in the order they did */
+fn stop_loads(p: passengers) -> [ stop_load ] {
+    p.loads
+}
+
+/* each agent's journey, one line per thing that happened */
+fn journey_log_csv(p: passengers) -> str {
+    let mut res = csv_row([ "agent_id", "time", "event", "stop_id", "trip_id" ]) + "\n";
+    for vec::each(p.riders) { |r|
+        for vec::each(r.log) { |e|
+            let trip_id = alt e.trip_id {
+                some(id) { id }
+                none { "" }
+            };
+            res += csv_row([ r.agent.id, time_format(e.time), e.what, e.stop_id, trip_id ]) + "\n";
+        }
+    }
+    ret res;
+}
@@ -0,0 +1,140 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import std::sort;
+import gtfs::{feed, feedaccess, direction};
+
+export pattern, find_patterns, direction_label;
+
+/*
+ * trips on a route which call at the same stops in the same order, in the
+ * same direction, share a pattern. patterns rather than trips are what
+ * timetables, headways and routing care about.
+ */
+
+type pattern = {
+    /* route_id, direction and a number; 1 is the pattern with most trips */
+    id: str,
+    name: str,
+    route_id: str,
+    direction: option<direction>,
+    stop_ids: [ str ],
+    /* ordered by first departure */
+    trip_ids: [ str ],
+    /* median scheduled time from the first arrival to each stop */
+    offsets: [ uint ],
+    /* median, fastest and slowest scheduled running times, end to end */
+    running_time: uint,
+    min_running_time: uint,
+    max_running_time: uint
+};
+
+fn direction_label(d: option<direction>) -> str {
+    alt d {
+        some(gtfs::oneway) { "0" }
+        some(gtfs::theotherway) { "1" }
+        none { "-" }
+    }
+}
+
+fn median(v: [ uint ]) -> uint {
+    let s = sort::merge_sort({|a, b| a <= b }, v);
+    s[vec::len(s) / 2u]
+}
+
+/* groups the given trips (which must have stop times loaded) into patterns,
+   ordered by route, direction, and then most trips first */
+fn find_patterns(feed: feed, trip_ids: [ str ]) -> [ @pattern ] {
+    type group = {
+        route_id: str,
+        direction: option<direction>,
+        stop_ids: [ str ],
+        mut trips: [ (uint, str) ]
+    };
+    let groups : map::hashmap<str, @group> = map::str_hash();
+    let mut keys = [];
+    for vec::each(trip_ids) { |trip_id|
+        let trip = feed.trips.get(trip_id);
+        let times = alt feed.stop_times.find(trip_id) {
+            some(times) { times }
+            none { cont; }
+        };
+        let stop_ids = vec::map(times) { |st| st.stop_id };
+        let key = str::connect([ trip.route_id, direction_label(trip.direction) ] + stop_ids, "\x1f");
+        if !groups.contains_key(key) {
+            groups.insert(key, @{
+                route_id: trip.route_id,
+                direction: trip.direction,
+                stop_ids: stop_ids,
+                mut trips: []
+            });
+            keys += [ key ];
+        }
+        let g = groups.get(key);
+        g.trips += [ (times[0].departure_time, trip_id) ];
+    }
+
+    let mut gs = vec::to_mut(vec::map(keys) { |k| groups.get(k) });
+    sort::quick_sort3({|a, b|
+        (a.route_id, direction_label(a.direction), -(vec::len(a.trips) as int), a.stop_ids) <
+            (b.route_id, direction_label(b.direction), -(vec::len(b.trips) as int), b.stop_ids)
+    }, {|a, b|
+        a.route_id == b.route_id && a.direction == b.direction && a.stop_ids == b.stop_ids
+    }, gs);
+
+    let names : map::set<str> = map::str_hash();
+    let mut res = [];
+    let mut n = 0u;
+    let mut prev = "";
+    for vec::each(gs) { |g|
+        let route_dir = g.route_id + "-" + direction_label(g.direction);
+        n = if route_dir == prev { n + 1u } else { 1u };
+        prev = route_dir;
+
+        let trips = sort::merge_sort({|a, b| a <= b }, g.trips);
+        let trip_ids = vec::map(trips) { |t| let (_, id) = t; id };
+
+        /* running times from the first arrival of each trip, which can't
+           come after any later time of the trip */
+        let nstops = vec::len(g.stop_ids);
+        let mut totals = [];
+        let mut by_stop = vec::to_mut(vec::from_elem(nstops, []));
+        for vec::each(trip_ids) { |id|
+            let times = feed.stop_times.get(id);
+            let start = times[0].arrival_time;
+            let since = { |t: uint| if t > start { t - start } else { 0u } };
+            for vec::eachi(times) { |i, st|
+                by_stop[i] += [ since(st.arrival_time) ];
+            }
+            totals += [ since(times[nstops - 1u].arrival_time) ];
+        }
+        let offsets = vec::map(vec::from_mut(by_stop)) { |v| median(v) };
+
+        let route = feed.routes.get(g.route_id);
+        let first = feed.stops.get(g.stop_ids[0]);
+        let last = feed.stops.get(g.stop_ids[nstops - 1u]);
+        let base = #fmt("%s %s to %s", route.short_name, first.name, last.name);
+        let mut name = base;
+        let mut dup = 2u;
+        while names.contains_key(name) {
+            name = #fmt("%s (%u)", base, dup);
+            dup += 1u;
+        }
+        map::set_add(names, name);
+
+        res += [ @{
+            id: #fmt("%s-%u", route_dir, n),
+            name: name,
+            route_id: g.route_id,
+            direction: g.direction,
+            stop_ids: g.stop_ids,
+            trip_ids: trip_ids,
+            offsets: offsets,
+            running_time: median(totals),
+            min_running_time: vec::foldl(uint::max_value, totals) { |a, b| uint::min(a, b) },
+            max_running_time: vec::foldl(0u, totals) { |a, b| uint::max(a, b) }
+        } ];
+    }
+    ret res;
+}
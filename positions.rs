@@ -0,0 +1,129 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::{feed, trip, stop_time, shape, point};
+import geom::{bearing, polyline_interpolate, haversine};
+
+export position, position_of, fleet, new_fleet, trip_started, trip_retimed, trip_ended,
+       positions_at;
+
+/*
+ * where a vehicle is between events. a vehicle waits at each stop from
+ * arrival to departure, and between stops moves at an even speed: along
+ * the trip's shape where the stop times say how far along it each stop
+ * is, otherwise in a straight line. the times are the trip's stop times,
+ * or under a delay model, as they're turning out.
+ */
+
+type position = {
+    trip_id: str,
+    route_id: str,
+    pt: point,
+    /* degrees clockwise from north */
+    bearing: float,
+    /* the stop it's standing at, if any */
+    stop_id: option<str>
+};
+
+/* the point `d` along the shape, and the bearing there */
+fn along_shape(s: @shape, d: float) -> (point, float) {
+    let n = vec::len(s.pts);
+    if n == 1u {
+        ret (s.pts[0], 0.);
+    }
+    /* the first segment ending at or beyond d */
+    let mut lo = 1u;
+    let mut hi = n - 1u;
+    while lo < hi {
+        let mid = (lo + hi) / 2u;
+        if s.dist_traveled[mid] < d { lo = mid + 1u; } else { hi = mid; }
+    }
+    let a = s.pts[lo - 1u];
+    let b = s.pts[lo];
+    let span = s.dist_traveled[lo] - s.dist_traveled[lo - 1u];
+    let f = if span <= 0. {
+        0.
+    } else {
+        float::fmin(1., float::fmax(0., (d - s.dist_traveled[lo - 1u]) / span))
+    };
+    ret ({ lat: a.lat + f * (b.lat - a.lat), lon: a.lon + f * (b.lon - a.lon) }, bearing(a, b));
+}
+
+/* where the trip's vehicle is at `t`, if it's running then */
+fn position_of(feed: feed, trip: @trip, times: [ mut @stop_time ], t: uint) -> option<position> {
+    let n = vec::len(times);
+    if n == 0u || t < times[0].arrival_time || t > times[n - 1u].departure_time {
+        ret none;
+    }
+    let at = { |pt: point, b: float, stop_id: option<str>|
+        some({ trip_id: trip.id, route_id: trip.route_id, pt: pt, bearing: b, stop_id: stop_id })
+    };
+    let shape = alt trip.shape_id {
+        some(id) { feed.shapes.find(id) }
+        none { none }
+    };
+    let mut i = 0u;
+    while i < n {
+        let st = times[i];
+        let here = feed.stops.get(st.stop_id).pt;
+        if t <= st.departure_time {
+            ret at(here, 0., some(st.stop_id));
+        }
+        if i + 1u < n && t < times[i + 1u].arrival_time {
+            let next = times[i + 1u];
+            let there = feed.stops.get(next.stop_id).pt;
+            let f = ((t - st.departure_time) as float)
+                / ((next.arrival_time - st.departure_time) as float);
+            alt (shape, st.shape_dist_travelled, next.shape_dist_travelled) {
+                (some(s), some(d0), some(d1)) {
+                    let (pt, b) = along_shape(s, d0 + f * (d1 - d0));
+                    ret at(pt, b, none);
+                }
+                _ {
+                    let pt = polyline_interpolate([ here, there ], f * haversine(here, there));
+                    ret at(pt, bearing(here, there), none);
+                }
+            }
+        }
+        i += 1u;
+    }
+    ret none;
+}
+
+/* the trips running in a simulation, keyed on trip id */
+type fleet = map::hashmap<str, (@trip, [ mut @stop_time ])>;
+
+fn new_fleet() -> fleet {
+    map::str_hash()
+}
+
+fn trip_started(f: fleet, trip: @trip, times: [ mut @stop_time ]) {
+    f.insert(trip.id, (trip, times));
+}
+
+/* the running trip's times have changed */
+fn trip_retimed(f: fleet, trip: @trip, times: [ mut @stop_time ]) {
+    if f.contains_key(trip.id) {
+        f.insert(trip.id, (trip, times));
+    }
+}
+
+fn trip_ended(f: fleet, trip: @trip) {
+    f.remove(trip.id);
+}
+
+/* positions of all the running vehicles at `t`, by trip id */
+fn positions_at(feed: feed, f: fleet, t: uint) -> [ position ] {
+    let mut ids = [];
+    for f.each_key() { |id| ids += [ id ]; }
+    let mut res = [];
+    for vec::each(std::sort::merge_sort({|a, b| a <= b }, ids)) { |id|
+        let (trip, times) = f.get(id);
+        alt position_of(feed, trip, times, t) {
+            some(p) { res += [ p ]; }
+            none {}
+        }
+    }
+    ret res;
+}
@@ -0,0 +1,125 @@
+export pqueue, new_pqueue, push, pop, peek_key, is_empty, len;
+
+/*
+ * a binary min-heap of values keyed on a uint. equal keys come out in the
+ * order they went in, so anything driven off one of these is repeatable.
+ */
+
+type entry<T> = {
+    key: uint,
+    seq: uint,
+    val: T
+};
+
+type pqueue<T> = @{
+    mut heap: [ mut entry<T> ],
+    mut next: uint
+};
+
+fn new_pqueue<T: copy>() -> pqueue<T> {
+    @{ mut heap: [ mut ], mut next: 0u }
+}
+
+fn len<T: copy>(q: pqueue<T>) -> uint {
+    vec::len(q.heap)
+}
+
+fn is_empty<T: copy>(q: pqueue<T>) -> bool {
+    vec::len(q.heap) == 0u
+}
+
+fn before<T: copy>(a: entry<T>, b: entry<T>) -> bool {
+    a.key < b.key || (a.key == b.key && a.seq < b.seq)
+}
+
+fn push<T: copy>(q: pqueue<T>, key: uint, val: T) {
+    q.heap += [ mut { key: key, seq: q.next, val: val } ];
+    q.next += 1u;
+    let mut i = vec::len(q.heap) - 1u;
+    while i > 0u {
+        let parent = (i - 1u) / 2u;
+        if !before(q.heap[i], q.heap[parent]) {
+            break;
+        }
+        q.heap[i] <-> q.heap[parent];
+        i = parent;
+    }
+}
+
+/* the key of the entry `pop` would return */
+fn peek_key<T: copy>(q: pqueue<T>) -> option<uint> {
+    if is_empty(q) { none } else { some(q.heap[0].key) }
+}
+
+/* removes the entry with the smallest key */
+fn pop<T: copy>(q: pqueue<T>) -> option<(uint, T)> {
+    let n = vec::len(q.heap);
+    if n == 0u {
+        ret none;
+    }
+    q.heap[0] <-> q.heap[n - 1u];
+    let top = vec::pop(q.heap);
+    let n = n - 1u;
+    let mut i = 0u;
+    loop {
+        let left = 2u * i + 1u;
+        let right = left + 1u;
+        let mut least = i;
+        if left < n && before(q.heap[left], q.heap[least]) {
+            least = left;
+        }
+        if right < n && before(q.heap[right], q.heap[least]) {
+            least = right;
+        }
+        if least == i {
+            break;
+        }
+        q.heap[i] <-> q.heap[least];
+        i = least;
+    }
+    ret some((top.key, top.val));
+}
+
+#[cfg(test)]
+mod tests {
+    import pqueue::{new_pqueue, push, pop, peek_key, is_empty, len};
+
+    #[test]
+    fn smallest_key_first_and_ties_in_order() {
+        let q = new_pqueue();
+        push(q, 5u, "a");
+        push(q, 3u, "b");
+        push(q, 5u, "c");
+        push(q, 3u, "d");
+        push(q, 5u, "e");
+        push(q, 1u, "f");
+        assert(len(q) == 6u);
+        assert(peek_key(q) == some(1u));
+        let mut out = [];
+        while !is_empty(q) {
+            let (k, v) = option::get(pop(q));
+            out += [ (k, v) ];
+        }
+        assert(out == [ (1u, "f"), (3u, "b"), (3u, "d"), (5u, "a"), (5u, "c"), (5u, "e") ]);
+        assert(pop(q) == none);
+    }
+
+    #[test]
+    fn ties_keep_order_across_pops() {
+        let q = new_pqueue();
+        let mut i = 0u;
+        while i < 20u {
+            push(q, i % 2u, i);
+            i += 1u;
+        }
+        /* popping some and pushing more at the same key keeps them behind */
+        assert(pop(q) == some((0u, 0u)));
+        push(q, 0u, 100u);
+        let mut zeros = [];
+        while peek_key(q) == some(0u) {
+            let (_, v) = option::get(pop(q));
+            zeros += [ v ];
+        }
+        assert(zeros == [ 2u, 4u, 6u, 8u, 10u, 12u, 14u, 16u, 18u, 100u ]);
+    }
+}
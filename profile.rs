@@ -0,0 +1,52 @@
+
+use gtfs;
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::gtfs_load;
+import gtfs::{feedaccess};
+import gtfs::csa::{build_timetable, stop_lookup, profile_between};
+import gtfs::transfer::{walking_transfers, default_walking, merge_transfers,
+                        load_feed_transfers, station_walk, min_change};
+import gtfs::osm::{load_osm, walkable};
+import gtfs::streets::{street_network, street_transfers};
+import gtfs::report::{csv_row};
+
+fn usage() {
+    io::println("usage: profile <gtfs dir> <from stop id> <to stop id> <YYYY-MM-DD> [osm file]");
+    io::println("       with an OSM extract (.osm or .osm.pbf), walks follow the streets");
+}
+
+/* prints, as CSV, every departure that isn't beaten by a later departure
+   arriving as early; travel time at any moment of the day follows from the
+   next departure in the list */
+fn main(args: [str])
+{
+    if vec::len(args) != 5u && vec::len(args) != 6u {
+        usage();
+        ret;
+    }
+    let feed = gtfs_load(args[1]);
+    let w = default_walking();
+    let transfers = if vec::len(args) == 6u {
+        street_transfers(feed, street_network(feed, load_osm(args[5], walkable)), w, some(station_walk))
+    } else {
+        walking_transfers(feed, w, some(station_walk))
+    };
+    merge_transfers(transfers, load_feed_transfers(feed, args[1], w));
+    let tt = build_timetable(feed, gtfs::parse_date(args[4]), transfers);
+    let stops = stop_lookup(tt);
+    let find = { |id: str|
+        alt stops.find(id) {
+            some(s) { s }
+            none { fail(#fmt("no such stop: %s", id)) }
+        }
+    };
+    io::println(csv_row([ "departure", "arrival", "minutes" ]));
+    for vec::each(profile_between(tt, find(args[2]), find(args[3]), min_change)) { |e|
+        let (dep, arr) = e;
+        io::println(csv_row([ gtfs::time_format(dep), gtfs::time_format(arr),
+                              uint::str((arr - dep + 59u) / 60u) ]));
+    }
+}
@@ -0,0 +1,419 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::{feed, feedaccess, date, weekday_of};
+import patterns::{pattern, find_patterns};
+import transfer::{transfers, paths_from};
+
+export network, build_network, leg, itinerary, plan, plan_between, earliest_arrival,
+       default_max_transfers;
+
+/*
+ * round-based public transit routing (RAPTOR; Delling, Pajor & Werneck,
+ * 2012) over one service day. each round rides one more vehicle, so the
+ * answers come out Pareto-optimal in arrival time and number of transfers.
+ * stops and routes are numbered; a route here is a trip pattern, and we
+ * assume trips within a pattern don't overtake each other.
+ */
+
+/* transfers a journey may make, unless asked for more or fewer */
+const default_max_transfers : uint = 4u;
+
+type trip_times = {
+    trip_id: str,
+    arrivals: [ uint ],
+    departures: [ uint ],
+    pickup: [ bool ],
+    drop_off: [ bool ]
+};
+
+type raptor_route = {
+    pattern: @pattern,
+    stops: [ uint ],
+    /* by departure from the first stop */
+    trips: [ trip_times ]
+};
+
+type network = {
+    date: date,
+    stop_ids: [ str ],
+    stop_index: map::hashmap<str, uint>,
+    routes: [ raptor_route ],
+    /* the routes calling at each stop, with the stop's position on each */
+    stop_routes: [ [ (uint, uint) ] ],
+    /* footpaths from each stop: (to, seconds) */
+    footpaths: [ [ (uint, uint) ] ]
+};
+
+/* a ride on a trip, or a walk when trip_id is none */
+type leg = {
+    trip_id: option<str>,
+    route_id: option<str>,
+    from_stop: str,
+    to_stop: str,
+    depart: uint,
+    arrive: uint
+};
+
+type itinerary = {
+    depart: uint,
+    arrive: uint,
+    transfers: uint,
+    legs: [ leg ]
+};
+
+fn build_network(feed: feed, date: date, footpaths: transfers) -> network {
+    let stop_index : map::hashmap<str, uint> = map::str_hash();
+    let mut stop_ids = [];
+    for feed.stops.each_key() { |id|
+        stop_index.insert(id, vec::len(stop_ids));
+        stop_ids += [ id ];
+    }
+    let n = vec::len(stop_ids);
+
+    let service_ids = feed.active_service_ids(weekday_of(date), date);
+    let pats = find_patterns(feed, feed.trip_ids_for_service_ids(service_ids));
+    let mut routes = [];
+    let mut stop_routes = vec::to_mut(vec::from_elem(n, []));
+    for vec::each(pats) { |p|
+        let r = vec::len(routes);
+        let stops = vec::map(p.stop_ids) { |id| stop_index.get(id) };
+        for vec::eachi(stops) { |pos, s|
+            stop_routes[s] += [ (r, pos) ];
+        }
+        let trips = vec::map(p.trip_ids) { |id|
+            let times = feed.stop_times.get(id);
+            {
+                trip_id: id,
+                arrivals: vec::map(times) { |st| st.arrival_time },
+                departures: vec::map(times) { |st| st.departure_time },
+                pickup: vec::map(times) { |st| marshal_allowed(st.pickup_type) },
+                drop_off: vec::map(times) { |st| marshal_allowed(st.drop_off_type) }
+            }
+        };
+        routes += [ { pattern: p, stops: stops, trips: trips } ];
+    }
+
+    let walks = vec::map(stop_ids) { |id|
+        let mut v = [];
+        for vec::each(paths_from(footpaths, id)) { |t|
+            alt stop_index.find(t.to) {
+                some(to) { v += [ (to, t.secs) ]; }
+                none {}
+            }
+        }
+        v
+    };
+
+    {
+        date: date,
+        stop_ids: stop_ids,
+        stop_index: stop_index,
+        routes: routes,
+        stop_routes: vec::from_mut(stop_routes),
+        footpaths: walks
+    }
+}
+
+/* how a stop was reached in a round */
+enum label_ref {
+    unreached,
+    /* from the origin, walking for this many seconds */
+    access(uint),
+    /* route, trip, boarding position, alighting position */
+    boarded(uint, uint, uint, uint),
+    /* from another stop reached in the same round, walking for this long */
+    walked(uint, uint)
+}
+
+type labels = {
+    arrivals: [ mut [ mut uint ] ],
+    refs: [ mut [ mut label_ref ] ]
+};
+
+fn is_ride(r: label_ref) -> bool {
+    alt r {
+        boarded(_, _, _, _) { true }
+        _ { false }
+    }
+}
+
+/* nothing arriving later than the best arrival at a target can help */
+fn bound(targets: [ (uint, uint) ], best: [ mut uint ]) -> uint {
+    let mut b = uint::max_value;
+    for vec::each(targets) { |t|
+        let (s, egress) = t;
+        if best[s] != uint::max_value {
+            b = uint::min(b, best[s] + egress);
+        }
+    }
+    ret b;
+}
+
+/* follows footpaths from the stops just reached in this round */
+fn walk_from(net: network, arrivals: [ mut uint ], refs: [ mut label_ref ],
+             best: [ mut uint ], marked: [ mut bool ], from: [ uint ],
+             targets: [ (uint, uint) ]) {
+    for vec::each(from) { |p|
+        for vec::each(net.footpaths[p]) { |fp|
+            let (q, secs) = fp;
+            let t = arrivals[p] + secs;
+            if t < best[q] && t < bound(targets, best) {
+                arrivals[q] = t;
+                refs[q] = walked(p, secs);
+                best[q] = t;
+                marked[q] = true;
+            }
+        }
+    }
+}
+
+/* the rounds themselves. `sources` and `targets` are (stop, walking
+   seconds) pairs; `change` is the time allowed to change between vehicles
+   at a stop */
+fn run(net: network, sources: [ (uint, uint) ], targets: [ (uint, uint) ],
+       depart: uint, max_transfers: uint, change: uint) -> labels {
+    let never = uint::max_value;
+    let n = vec::len(net.stop_ids);
+    let rounds = max_transfers + 2u;
+    let arrivals = vec::to_mut(vec::from_fn(rounds) { |_k| vec::to_mut(vec::from_elem(n, never)) });
+    let refs = vec::to_mut(vec::from_fn(rounds) { |_k| vec::to_mut(vec::from_elem(n, unreached)) });
+    let best = vec::to_mut(vec::from_elem(n, never));
+    let marked = vec::to_mut(vec::from_elem(n, false));
+
+    let mut reached = [];
+    for vec::each(sources) { |src|
+        let (s, secs) = src;
+        let t = depart + secs;
+        if t < arrivals[0][s] {
+            arrivals[0][s] = t;
+            refs[0][s] = access(secs);
+            best[s] = t;
+            marked[s] = true;
+            reached += [ s ];
+        }
+    }
+    walk_from(net, arrivals[0], refs[0], best, marked, reached, targets);
+
+    let mut k = 1u;
+    while k < rounds {
+        /* each route to scan, from the earliest marked stop on it */
+        let queue : map::hashmap<uint, uint> = map::uint_hash();
+        let mut s = 0u;
+        while s < n {
+            if marked[s] {
+                marked[s] = false;
+                for vec::each(net.stop_routes[s]) { |rp|
+                    let (r, pos) = rp;
+                    alt queue.find(r) {
+                        some(p) if p <= pos {}
+                        _ { queue.insert(r, pos); }
+                    }
+                }
+            }
+            s += 1u;
+        }
+        if queue.size() == 0u {
+            break;
+        }
+
+        let mut improved = [];
+        for queue.each() { |r, start|
+            let route = net.routes[r];
+            /* (trip, boarding position) */
+            let mut current : option<(uint, uint)> = none;
+            let mut pos = start;
+            while pos < vec::len(route.stops) {
+                let s = route.stops[pos];
+                alt current {
+                    some((t, b)) {
+                        let trip = route.trips[t];
+                        let a = trip.arrivals[pos];
+                        if trip.drop_off[pos] && a < best[s] && a < bound(targets, best) {
+                            arrivals[k][s] = a;
+                            refs[k][s] = boarded(r, t, b, pos);
+                            best[s] = a;
+                            if !marked[s] {
+                                marked[s] = true;
+                                improved += [ s ];
+                            }
+                        }
+                    }
+                    none {}
+                }
+                /* can we catch an earlier trip here? */
+                let prev = arrivals[k - 1u][s];
+                if prev != never {
+                    let ready = if is_ride(refs[k - 1u][s]) { prev + change } else { prev };
+                    let limit = alt current {
+                        some((t, _)) { t }
+                        none { vec::len(route.trips) }
+                    };
+                    let mut t = 0u;
+                    while t < limit {
+                        let trip = route.trips[t];
+                        if trip.pickup[pos] && trip.departures[pos] >= ready {
+                            current = some((t, pos));
+                            break;
+                        }
+                        t += 1u;
+                    }
+                }
+                pos += 1u;
+            }
+        }
+        walk_from(net, arrivals[k], refs[k], best, marked, improved, targets);
+        k += 1u;
+    }
+    ret { arrivals: arrivals, refs: refs };
+}
+
+/* legs taken to reach the stop in round k, in order */
+fn reconstruct(net: network, labels: labels, round: uint, stop: uint) -> [ leg ] {
+    let mut legs = [];
+    let mut k = round;
+    let mut s = stop;
+    loop {
+        alt labels.refs[k][s] {
+            access(_) {
+                break;
+            }
+            boarded(r, t, b, a) {
+                let route = net.routes[r];
+                let trip = route.trips[t];
+                legs += [ {
+                    trip_id: some(trip.trip_id),
+                    route_id: some(route.pattern.route_id),
+                    from_stop: net.stop_ids[route.stops[b]],
+                    to_stop: net.stop_ids[s],
+                    depart: trip.departures[b],
+                    arrive: trip.arrivals[a]
+                } ];
+                s = route.stops[b];
+                k -= 1u;
+            }
+            walked(p, secs) {
+                let arrive = labels.arrivals[k][s];
+                legs += [ {
+                    trip_id: none,
+                    route_id: none,
+                    from_stop: net.stop_ids[p],
+                    to_stop: net.stop_ids[s],
+                    depart: arrive - secs,
+                    arrive: arrive
+                } ];
+                s = p;
+            }
+            unreached {
+                fail("reconstruct: stop wasn't reached");
+            }
+        }
+    }
+    ret vec::reversed(legs);
+}
+
+fn lookup(net: network, places: [ (str, uint) ]) -> [ (uint, uint) ] {
+    vec::map(places) { |p|
+        let (id, secs) = p;
+        alt net.stop_index.find(id) {
+            some(s) { (s, secs) }
+            none { fail(#fmt("unknown stop: %s", id)) }
+        }
+    }
+}
+
+/* journeys from any of the `sources` to any of the `targets`, each a
+   (stop_id, walking seconds) pair. one itinerary for each number of
+   transfers that arrives earlier than all those with fewer */
+fn plan_between(net: network, sources: [ (str, uint) ], targets: [ (str, uint) ],
+                depart: uint, max_transfers: uint, change: uint) -> [ itinerary ] {
+    let never = uint::max_value;
+    let tgts = lookup(net, targets);
+    let labels = run(net, lookup(net, sources), tgts, depart, max_transfers, change);
+    let mut res = [];
+    let mut best = never;
+    let mut k = 0u;
+    while k < vec::len(labels.arrivals) {
+        let mut round_best = never;
+        let mut round_stop = 0u;
+        for vec::each(tgts) { |t|
+            let (s, egress) = t;
+            let a = labels.arrivals[k][s];
+            if a != never && a + egress < round_best {
+                round_best = a + egress;
+                round_stop = s;
+            }
+        }
+        if round_best < best {
+            best = round_best;
+            let legs = reconstruct(net, labels, k, round_stop);
+            res += [ {
+                depart: if vec::len(legs) > 0u { legs[0].depart } else { depart },
+                arrive: round_best,
+                transfers: if k > 0u { k - 1u } else { 0u },
+                legs: legs
+            } ];
+        }
+        k += 1u;
+    }
+    ret res;
+}
+
+fn plan(net: network, from: str, to: str, depart: uint, max_transfers: uint, change: uint) -> [ itinerary ] {
+    plan_between(net, [ (from, 0u) ], [ (to, 0u) ], depart, max_transfers, change)
+}
+
+fn earliest_arrival(net: network, from: str, to: str, depart: uint, max_transfers: uint, change: uint) -> option<itinerary> {
+    let its = plan(net, from, to, depart, max_transfers, change);
+    if vec::len(its) == 0u {
+        ret none;
+    }
+    ret some(its[vec::len(its) - 1u]);
+}
+
+#[cfg(test)]
+mod tests {
+    import raptor::{network, build_network, plan};
+    import transfer::{new_transfers};
+    import testfeed::{test_feed, test_date, add_stop, add_trip};
+
+    /* a slow route straight from a to d, 08:00 to 09:00, and a quicker
+       way changing at b: a to b 08:00 to 08:10, then b to d leaving at
+       `change_at` and in at 08:30 */
+    fn two_ways(change_at: uint) -> network {
+        let f = test_feed();
+        for vec::each([ "a", "b", "d" ]) { |id| add_stop(f, id, 0., 0.); }
+        add_trip(f, "slow", "slow-1", [ ("a", 28800u, 28800u), ("d", 32400u, 32400u) ]);
+        add_trip(f, "first", "first-1", [ ("a", 28800u, 28800u), ("b", 29400u, 29400u) ]);
+        add_trip(f, "second", "second-1", [ ("b", change_at, change_at), ("d", 30600u, 30600u) ]);
+        build_network(f, test_date(), new_transfers())
+    }
+
+    #[test]
+    fn each_round_rides_once_more() {
+        let its = plan(two_ways(30000u), "a", "d", 28500u, 4u, 60u);
+        assert(vec::len(its) == 2u);
+        assert(its[0].transfers == 0u && its[0].arrive == 32400u);
+        assert(vec::len(its[0].legs) == 1u && its[0].legs[0].trip_id == some("slow-1"));
+        assert(its[1].transfers == 1u && its[1].arrive == 30600u);
+        assert(vec::len(its[1].legs) == 2u);
+        assert(its[1].legs[0].trip_id == some("first-1") && its[1].legs[0].to_stop == "b");
+        assert(its[1].legs[1].trip_id == some("second-1") && its[1].legs[1].depart == 30000u);
+    }
+
+    #[test]
+    fn too_short_a_change_is_missed() {
+        /* thirty seconds at b, with a minute needed */
+        let its = plan(two_ways(29430u), "a", "d", 28500u, 4u, 60u);
+        assert(vec::len(its) == 1u);
+        assert(its[0].transfers == 0u && its[0].arrive == 32400u);
+    }
+
+    #[test]
+    fn no_transfers_allowed() {
+        let its = plan(two_ways(30000u), "a", "d", 28500u, 0u, 60u);
+        assert(vec::len(its) == 1u);
+        assert(its[0].arrive == 32400u);
+    }
+}
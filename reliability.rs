@@ -0,0 +1,406 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import std::sort;
+import gtfs::{feed, trip, stop_time};
+import daysim::{observer, run_day};
+import fleet::{duty};
+import positions::{fleet};
+import delay::{spread, default_delays};
+import passenger::{agent, new_passengers, add_agent, finish, outcomes};
+import crowding::{capacities};
+import rng::{rng, fork};
+import report::{csv_row};
+
+export reliability, new_reliability, recorder, new_recorder, add_run, monte_carlo,
+       routes_csv, timepoints_csv, transfers_csv;
+
+/*
+ * how reliable the service is, over many runs of the same day with
+ * different luck. each run is recorded call by call, and the runs are
+ * summed up: how often each route is on time at its timepoints, how late
+ * it runs there, how evenly its vehicles are spaced, and how often
+ * connections between routes are missed. passengers can ride along, so
+ * that vehicles stand at stops for as long as boarding takes.
+ *
+ * timepoints are each trip's first and last stops, and any stop the
+ * timetable waits at. a vehicle is on time leaving a timepoint, or
+ * arriving at its last stop, from a minute early to five late.
+ */
+
+const ontime_early : int = 60;
+const ontime_late : int = 300;
+/* a connection is planned to the first departure of each other route
+   leaving at least min_change after arriving, and at most this much more */
+const transfer_window : uint = 900u;
+
+/* a vehicle calling at a stop */
+type call = {
+    trip_id: str,
+    route_id: str,
+    stop_id: str,
+    sequence: uint,
+    sched_arrive: uint,
+    sched_depart: uint,
+    arrive: uint,
+    depart: uint,
+    first: bool,
+    last: bool
+};
+
+fn timepoint(c: call) -> bool {
+    c.first || c.last || c.sched_depart > c.sched_arrive
+}
+
+/* how late the call was where it counts: arriving at the end, else leaving */
+fn lateness(c: call) -> int {
+    if c.last {
+        (c.arrive as int) - (c.sched_arrive as int)
+    } else {
+        (c.depart as int) - (c.sched_depart as int)
+    }
+}
+
+/* one run's calls, in the order vehicles left */
+type recorder = @{
+    mut calls: [ mut call ],
+    /* trips started but not yet away from their first stop */
+    starting: map::set<str>
+};
+
+fn new_recorder() -> recorder {
+    @{ mut calls: [ mut ], starting: map::str_hash() }
+}
+
+impl of observer for recorder {
+    fn trip_started(_now: uint, trip: @trip) {
+        map::set_add(self.starting, trip.id);
+    }
+    fn arrived(_now: uint, _trip: @trip, _st: @stop_time) -> (uint, uint) {
+        (0u, 0u)
+    }
+    fn departed(now: uint, trip: @trip, st: @stop_time, dwell: uint) {
+        let first = self.starting.contains_key(trip.id);
+        if first {
+            self.starting.remove(trip.id);
+        }
+        self.calls += [ mut {
+            trip_id: trip.id,
+            route_id: trip.route_id,
+            stop_id: st.stop_id,
+            sequence: st.sequence,
+            sched_arrive: st.arrival_time,
+            sched_depart: st.departure_time,
+            arrive: now - dwell,
+            depart: now,
+            first: first,
+            last: false
+        } ];
+    }
+    /* a trip ends as soon as it leaves its last stop, so that was the
+       last call recorded */
+    fn trip_ended(_now: uint, _trip: @trip) {
+        let n = vec::len(self.calls);
+        self.calls[n - 1u] = { last: true with self.calls[n - 1u] };
+    }
+    fn layover(_now: uint, _block_id: str, _trip: @trip, _next: @trip, _secs: uint) {
+    }
+    fn snapshot(_now: uint, _running: fleet) {
+    }
+}
+
+type route_stats = {
+    mut calls: uint,
+    mut on_time: uint,
+    mut early: uint,
+    mut late: uint
+};
+
+/* running sums of the headways at one stop of one route */
+type headway_stats = {
+    route_id: str,
+    mut scheduled: (uint, float, float),
+    mut actual: (uint, float, float)
+};
+
+type delay_stats = {
+    route_id: str,
+    stop_id: str,
+    mut samples: [ int ]
+};
+
+type transfer_stats = {
+    from_route_id: str,
+    to_route_id: str,
+    mut planned: uint,
+    mut missed: uint
+};
+
+type reliability = @{
+    min_change: uint,
+    mut runs: uint,
+    routes: map::hashmap<str, @route_stats>,
+    /* keyed on "route_id stop_id" */
+    delays: map::hashmap<str, @delay_stats>,
+    headways: map::hashmap<str, @headway_stats>,
+    /* keyed on "from_route_id to_route_id" */
+    transfers: map::hashmap<str, @transfer_stats>,
+    /* passengers reaching their destination, and not, over all runs */
+    mut arrived: uint,
+    mut stranded: uint
+};
+
+/* `min_change` is the least time needed to change vehicles at a stop */
+fn new_reliability(min_change: uint) -> reliability {
+    @{
+        min_change: min_change,
+        mut runs: 0u,
+        routes: map::str_hash(),
+        delays: map::str_hash(),
+        headways: map::str_hash(),
+        transfers: map::str_hash(),
+        mut arrived: 0u,
+        mut stranded: 0u
+    }
+}
+
+fn add_to<T: copy>(m: map::hashmap<str, [ T ]>, key: str, v: T) {
+    let prev = alt m.find(key) {
+        some(p) { p }
+        none { [] }
+    };
+    m.insert(key, prev + [ v ]);
+}
+
+fn add_sample(sums: (uint, float, float), x: float) -> (uint, float, float) {
+    let (n, sum, sumsq) = sums;
+    (n + 1u, sum + x, sumsq + x * x)
+}
+
+/* the coefficient of variation of what's been summed, if there's enough */
+fn cv(sums: (uint, float, float)) -> option<float> {
+    let (n, sum, sumsq) = sums;
+    if n < 2u || sum <= 0. {
+        ret none;
+    }
+    let mean = sum / (n as float);
+    let var = float::fmax(0., sumsq / (n as float) - mean * mean);
+    some(float::sqrt(var) / mean)
+}
+
+fn headways_at(h: @headway_stats, departures: [ (uint, uint) ]) {
+    let sched = sort::merge_sort({|a, b| a <= b }, vec::map(departures) { |d| let (s, _) = d; s });
+    let actual = sort::merge_sort({|a, b| a <= b }, vec::map(departures) { |d| let (_, a) = d; a });
+    let mut i = 1u;
+    while i < vec::len(sched) {
+        h.scheduled = add_sample(h.scheduled, (sched[i] - sched[i - 1u]) as float);
+        h.actual = add_sample(h.actual, (actual[i] - actual[i - 1u]) as float);
+        i += 1u;
+    }
+}
+
+/* the planned connections at one stop, and which were missed */
+fn transfers_at(rel: reliability, arrivals: [ call ], departures: [ call ]) {
+    let departures = sort::merge_sort({|a, b| a.sched_depart <= b.sched_depart }, departures);
+    let n = vec::len(departures);
+    for vec::each(arrivals) { |a|
+        let earliest = a.sched_arrive + rel.min_change;
+        /* the first departure early enough */
+        let mut lo = 0u;
+        let mut hi = n;
+        while lo < hi {
+            let mid = (lo + hi) / 2u;
+            if departures[mid].sched_depart < earliest { lo = mid + 1u; } else { hi = mid; }
+        }
+        let mut seen = [];
+        let mut j = lo;
+        while j < n && departures[j].sched_depart <= earliest + transfer_window {
+            let d = departures[j];
+            if d.route_id != a.route_id && !vec::contains(seen, d.route_id) {
+                seen += [ d.route_id ];
+                let key = a.route_id + " " + d.route_id;
+                let t = alt rel.transfers.find(key) {
+                    some(t) { t }
+                    none {
+                        let t = @{ from_route_id: a.route_id, to_route_id: d.route_id,
+                                   mut planned: 0u, mut missed: 0u };
+                        rel.transfers.insert(key, t);
+                        t
+                    }
+                };
+                t.planned += 1u;
+                if d.depart < a.arrive + rel.min_change {
+                    t.missed += 1u;
+                }
+            }
+            j += 1u;
+        }
+    }
+}
+
+/* sums up one run */
+fn add_run(rel: reliability, rec: recorder) {
+    rel.runs += 1u;
+    let departures : map::hashmap<str, [ (uint, uint) ]> = map::str_hash();
+    let route_of : map::hashmap<str, str> = map::str_hash();
+    let arriving : map::hashmap<str, [ call ]> = map::str_hash();
+    let leaving : map::hashmap<str, [ call ]> = map::str_hash();
+    for vec::each(rec.calls) { |c|
+        let key = c.route_id + " " + c.stop_id;
+        if timepoint(c) {
+            let r = alt rel.routes.find(c.route_id) {
+                some(r) { r }
+                none {
+                    let r = @{ mut calls: 0u, mut on_time: 0u, mut early: 0u, mut late: 0u };
+                    rel.routes.insert(c.route_id, r);
+                    r
+                }
+            };
+            let l = lateness(c);
+            r.calls += 1u;
+            if l < -ontime_early {
+                r.early += 1u;
+            } else if l > ontime_late {
+                r.late += 1u;
+            } else {
+                r.on_time += 1u;
+            }
+            let d = alt rel.delays.find(key) {
+                some(d) { d }
+                none {
+                    let d = @{ route_id: c.route_id, stop_id: c.stop_id, mut samples: [] };
+                    rel.delays.insert(key, d);
+                    d
+                }
+            };
+            d.samples += [ l ];
+        }
+        if !c.last {
+            add_to(departures, key, (c.sched_depart, c.depart));
+            route_of.insert(key, c.route_id);
+            add_to(leaving, c.stop_id, c);
+        }
+        if !c.first {
+            add_to(arriving, c.stop_id, c);
+        }
+    }
+    for departures.each { |key, v|
+        let h = alt rel.headways.find(key) {
+            some(h) { h }
+            none {
+                let h = @{ route_id: route_of.get(key), mut scheduled: (0u, 0., 0.), mut actual: (0u, 0., 0.) };
+                rel.headways.insert(key, h);
+                h
+            }
+        };
+        headways_at(h, v);
+    }
+    for arriving.each { |stop_id, v|
+        alt leaving.find(stop_id) {
+            some(d) { transfers_at(rel, v, d); }
+            none {}
+        }
+    }
+}
+
+/* runs the day `runs` times, each with delays drawn from a generator
+   forked from `r`, and each with the given agents riding afresh */
+fn monte_carlo(feed: feed, trips: [ (@trip, [ mut @stop_time ]) ], duties: [ duty ],
+               runs: uint, r: rng, segments: map::hashmap<str, spread>,
+               agents: [ agent ], capacities: capacities, min_change: uint) -> reliability {
+    let rel = new_reliability(min_change);
+    let mut i = 0u;
+    while i < runs {
+        let rec = new_recorder();
+        let p = new_passengers(feed, capacities);
+        for vec::each(agents) { |a| add_agent(p, a); }
+        run_day(feed, trips, duties, [ rec as observer, p as observer ],
+                default_delays(fork(r), segments), none);
+        finish(p);
+        let (arrived, stranded) = outcomes(p);
+        rel.arrived += arrived;
+        rel.stranded += stranded;
+        add_run(rel, rec);
+        i += 1u;
+    }
+    ret rel;
+}
+
+fn sorted_keys<T: copy>(m: map::hashmap<str, T>) -> [ str ] {
+    let mut keys = [];
+    for m.each_key() { |k| keys += [ k ]; }
+    sort::merge_sort({|a, b| a <= b }, keys)
+}
+
+fn pct(n: uint, total: uint) -> str {
+    if total == 0u { "" } else { #fmt("%.1f", 100. * (n as float) / (total as float)) }
+}
+
+/* per route: punctuality at timepoints, and the mean coefficient of
+   variation of headways over its stops, as scheduled and as run */
+fn routes_csv(rel: reliability) -> str {
+    let cvs : map::hashmap<str, [ (float, float) ]> = map::str_hash();
+    for rel.headways.each_value() { |h|
+        alt (cv(h.scheduled), cv(h.actual)) {
+            (some(s), some(a)) { add_to(cvs, h.route_id, (s, a)); }
+            _ {}
+        }
+    }
+    let mut res = csv_row([ "route_id", "runs", "timepoint_calls", "on_time_pct", "early_pct",
+                            "late_pct", "headway_cv_scheduled", "headway_cv_actual" ]) + "\n";
+    for vec::each(sorted_keys(rel.routes)) { |id|
+        let r = rel.routes.get(id);
+        let (sched, actual) = alt cvs.find(id) {
+            some(v) {
+                let n = vec::len(v) as float;
+                let mut s = 0.;
+                let mut a = 0.;
+                for vec::each(v) { |sa| let (x, y) = sa; s += x; a += y; }
+                (#fmt("%.3f", s / n), #fmt("%.3f", a / n))
+            }
+            none { ("", "") }
+        };
+        res += csv_row([ id, uint::str(rel.runs), uint::str(r.calls), pct(r.on_time, r.calls),
+                         pct(r.early, r.calls), pct(r.late, r.calls), sched, actual ]) + "\n";
+    }
+    ret res;
+}
+
+fn int_percentile(sorted: [ int ], p: uint) -> int {
+    let n = vec::len(sorted);
+    let rank = (p * n + 99u) / 100u;
+    sorted[if rank == 0u { 0u } else { rank - 1u }]
+}
+
+/* lateness in seconds at each route's timepoints, over all runs */
+fn timepoints_csv(rel: reliability) -> str {
+    let percentiles = [ 5u, 50u, 90u, 95u ];
+    let header = [ "route_id", "stop_id", "samples", "mean_delay" ]
+        + vec::map(percentiles) { |p| #fmt("p%u", p) } + [ "max" ];
+    let mut res = csv_row(header) + "\n";
+    for vec::each(sorted_keys(rel.delays)) { |key|
+        let d = rel.delays.get(key);
+        let sorted = sort::merge_sort({|a, b| a <= b }, d.samples);
+        let n = vec::len(sorted);
+        let mut sum = 0;
+        for vec::each(sorted) { |l| sum += l; }
+        res += csv_row([ d.route_id, d.stop_id, uint::str(n), #fmt("%.1f", (sum as float) / (n as float)) ]
+                       + vec::map(percentiles) { |p| int::str(int_percentile(sorted, p)) }
+                       + [ int::str(sorted[n - 1u]) ]) + "\n";
+    }
+    ret res;
+}
+
+/* planned connections between routes, per run, and how many were missed */
+fn transfers_csv(rel: reliability) -> str {
+    let mut res = csv_row([ "from_route_id", "to_route_id", "connections_per_run",
+                            "missed_per_run", "missed_pct" ]) + "\n";
+    let runs = uint::max(1u, rel.runs) as float;
+    for vec::each(sorted_keys(rel.transfers)) { |key|
+        let t = rel.transfers.get(key);
+        res += csv_row([ t.from_route_id, t.to_route_id, #fmt("%.1f", (t.planned as float) / runs),
+                         #fmt("%.1f", (t.missed as float) / runs), pct(t.missed, t.planned) ]) + "\n";
+    }
+    ret res;
+}
@@ -0,0 +1,37 @@
+use std;
+
+export csv_field, csv_row, html_escape, write_file;
+
+/* quotes a field if it needs it */
+fn csv_field(s: str) -> str {
+    if str::find_char(s, ',') == none && str::find_char(s, '"') == none &&
+            str::find_char(s, '\n') == none {
+        ret s;
+    }
+    ret "\"" + str::replace(s, "\"", "\"\"") + "\"";
+}
+
+fn csv_row(fields: [ str ]) -> str {
+    str::connect(vec::map(fields) { |f| csv_field(f) }, ",")
+}
+
+fn html_escape(s: str) -> str {
+    let mut r = "";
+    str::iter(s) { |c|
+        alt c {
+            '&' { r += "&amp;"; }
+            '<' { r += "&lt;"; }
+            '>' { r += "&gt;"; }
+            '"' { r += "&quot;"; }
+            _ { str::push_char(r, c); }
+        }
+    };
+    ret r;
+}
+
+fn write_file(path: str, contents: str) {
+    alt io::file_writer(path, [ io::create, io::truncate ]) {
+        result::ok(w) { w.write_str(contents); }
+        result::err(e) { fail(#fmt("cannot write %s: %s", path, e)); }
+    }
+}
@@ -0,0 +1,76 @@
+
+use gtfs;
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::gtfs_load;
+import gtfs::{feedaccess};
+import gtfs::raptor::{build_network, default_max_transfers};
+import gtfs::transfer::{walking_transfers, default_walking, merge_transfers,
+                        load_feed_transfers, station_walk, min_change};
+import gtfs::daysim::{observer, day_trips, run_day};
+import gtfs::fleet::{blocks};
+import gtfs::passenger::{load_requests, plan_agent, new_passengers, add_agent, finish,
+                         outcomes, stop_loads, journey_log_csv};
+import gtfs::crowding::{default_capacities, load_capacities, load_profile_csv, crowding_csv};
+import gtfs::rng::{new_rng};
+import gtfs::delay::{no_delays, default_delays, load_segment_spreads};
+import gtfs::report::{write_file};
+
+fn usage() {
+    io::println("usage: ridesim <gtfs dir> <YYYY-MM-DD> <agents csv> <output dir> [capacities csv|-] [seed [segments csv]]");
+    io::println("       agents have agent_id, origin_stop_id, destination_stop_id and departure_time columns");
+    io::println("       capacities have a capacity column, and mode, route_id or trip_id");
+    io::println("       with a seed, vehicles run late; segments have from_stop_id, to_stop_id, median and sigma");
+    io::println("       writes journeys.csv, loads.csv and crowding.csv");
+}
+
+fn main(args: [str])
+{
+    if vec::len(args) < 5u || vec::len(args) > 8u {
+        usage();
+        ret;
+    }
+    let feed = gtfs_load(args[1]);
+    let date = gtfs::parse_date(args[2]);
+    let service_ids = feed.active_service_ids(gtfs::weekday_of(date), date);
+    let trip_ids = vec::filter(feed.trip_ids_for_service_ids(service_ids)) { |id|
+        feed.stop_times.contains_key(id)
+    };
+
+    let transfers = walking_transfers(feed, default_walking(), some(station_walk));
+    merge_transfers(transfers, load_feed_transfers(feed, args[1], default_walking()));
+    let net = build_network(feed, date, transfers);
+    let capacities = if vec::len(args) > 5u && args[5] != "-" {
+        load_capacities(args[5])
+    } else {
+        default_capacities()
+    };
+    let delays = if vec::len(args) > 6u {
+        let seed = alt uint::from_str(args[6]) {
+            some(n) { n }
+            none { usage(); ret; }
+        };
+        let segments = if vec::len(args) == 8u { load_segment_spreads(args[7]) } else { map::str_hash() };
+        default_delays(new_rng(seed as u64), segments)
+    } else {
+        no_delays()
+    };
+    let p = new_passengers(feed, capacities);
+    let mut unplanned = 0u;
+    for vec::each(load_requests(args[3])) { |r|
+        alt plan_agent(net, r, default_max_transfers, min_change) {
+            some(a) { add_agent(p, a); }
+            none { unplanned += 1u; }
+        }
+    }
+
+    run_day(feed, day_trips(feed, trip_ids), blocks(feed, trip_ids), [ p as observer ], delays, none);
+    finish(p);
+    let (arrived, stranded) = outcomes(p);
+    io::println(#fmt("%u arrived, %u stranded, %u with no journey", arrived, stranded, unplanned));
+    write_file(path::connect(args[4], "journeys.csv"), journey_log_csv(p));
+    write_file(path::connect(args[4], "loads.csv"), load_profile_csv(stop_loads(p)));
+    write_file(path::connect(args[4], "crowding.csv"), crowding_csv(stop_loads(p)));
+}
@@ -0,0 +1,73 @@
+export rng, new_rng, fork, next_u64, uniform, below, exponential, normal, lognormal, poisson;
+
+/*
+ * a small seeded random number generator (splitmix64), so a simulation
+ * run can be repeated exactly. not for anything needing real randomness.
+ */
+
+type rng = @{ mut state: u64 };
+
+fn new_rng(seed: u64) -> rng {
+    @{ mut state: seed }
+}
+
+/* a new generator, seeded from this one, for an independent stream */
+fn fork(r: rng) -> rng {
+    new_rng(next_u64(r))
+}
+
+fn next_u64(r: rng) -> u64 {
+    r.state += 0x9e3779b97f4a7c15u64;
+    let mut z = r.state;
+    z = (z ^ (z >> 30u64)) * 0xbf58476d1ce4e5b9u64;
+    z = (z ^ (z >> 27u64)) * 0x94d049bb133111ebu64;
+    z ^ (z >> 31u64)
+}
+
+/* in [0, 1) */
+fn uniform(r: rng) -> float {
+    ((next_u64(r) >> 11u64) as float) / 9007199254740992.
+}
+
+/* in [0, n) */
+fn below(r: rng, n: uint) -> uint {
+    if n == 0u {
+        fail("rng: below(0)");
+    }
+    uint::min((uniform(r) * (n as float)) as uint, n - 1u)
+}
+
+fn exponential(r: rng, mean: float) -> float {
+    -mean * float::ln(1. - uniform(r))
+}
+
+fn normal(r: rng, mean: float, sd: float) -> float {
+    /* box-muller; 1 - u keeps the log finite */
+    let u1 = 1. - uniform(r);
+    let u2 = uniform(r);
+    mean + sd * float::sqrt(-2. * float::ln(u1)) * float::cos(2. * float::consts::pi * u2)
+}
+
+/* positive, with the given median, and spread: sigma is the standard
+   deviation of its log */
+fn lognormal(r: rng, median: float, sigma: float) -> float {
+    median * float::exp(normal(r, 0., sigma))
+}
+
+fn poisson(r: rng, mean: float) -> uint {
+    if mean <= 0. {
+        ret 0u;
+    }
+    if mean > 30. {
+        /* near enough normal by now */
+        ret float::fmax(0., float::floor(normal(r, mean, float::sqrt(mean)) + 0.5)) as uint;
+    }
+    let limit = float::exp(-mean);
+    let mut k = 0u;
+    let mut p = uniform(r);
+    while p > limit {
+        k += 1u;
+        p *= uniform(r);
+    }
+    ret k;
+}
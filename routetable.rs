@@ -0,0 +1,38 @@
+
+use gtfs;
+import gtfs::gtfs_load;
+import gtfs::{feedaccess};
+import gtfs::timetable::{route_timetable, format_text, format_csv, format_html};
+
+fn usage() {
+    io::println("usage: routetable <gtfs dir> <route id> <0|1|all> <YYYY-MM-DD> [text|csv|html]");
+}
+
+fn main(args: [str])
+{
+    if vec::len(args) < 5u || vec::len(args) > 6u {
+        usage();
+        ret;
+    }
+    let route_id = args[2];
+    let direction = alt args[3] {
+        "0" { some(gtfs::oneway) }
+        "1" { some(gtfs::theotherway) }
+        "all" { none }
+        _ { usage(); ret; }
+    };
+    let date = gtfs::parse_date(args[4]);
+    let format = if vec::len(args) == 6u { args[5] } else { "text" };
+
+    let feed = gtfs_load(args[1]);
+    if !feed.routes.contains_key(route_id) {
+        fail(#fmt("no such route: %s", route_id));
+    }
+    let tt = route_timetable(feed, route_id, direction, date);
+    io::print(alt format {
+        "text" { format_text(tt) }
+        "csv" { format_csv(tt) }
+        "html" { format_html(tt) }
+        _ { usage(); ret; }
+    });
+}
@@ -0,0 +1,220 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import std::sort;
+import gtfs::{feed, feedaccess, point, shape, route_type, marshal, time_format};
+import geom::{haversine};
+import osm::{osm, drivable, rail_track, tram_track};
+import streets::{street_graph, build_graph, snap, path_between};
+import patterns::{pattern, find_patterns};
+import report::{csv_row};
+
+export networks, build_networks, wanted_way, pattern_shape, trip_shape, generate_shapes,
+       shapes_csv, trips_csv, stop_times_csv;
+
+/*
+ * shapes for feeds which don't have any: each pattern is routed stop to
+ * stop over the OSM network its vehicles use. where a stop is off the
+ * network, or no route is found, the shape goes straight to the stop. the
+ * feed's trips and stop times are written back out with the shape each
+ * trip follows and how far along it each stop lies.
+ */
+
+/* a stop further than this from the network isn't matched to it */
+const snap_metres : float = 100.;
+
+/* the graphs vehicles of each mode can follow */
+type networks = {
+    road: @street_graph,
+    rail: @street_graph,
+    tram: @street_graph
+};
+
+/* ways in any of the networks; pass to load_osm */
+fn wanted_way(tags: [ (str, str) ]) -> bool {
+    drivable(tags) || rail_track(tags) || tram_track(tags)
+}
+
+fn build_networks(o: osm) -> networks {
+    let only = { |f: fn@([ (str, str) ]) -> bool|
+        { nodes: o.nodes, ways: vec::filter(o.ways) { |w| f(w.tags) } }
+    };
+    {
+        road: build_graph(only(drivable), true),
+        rail: build_graph(only(rail_track), false),
+        tram: build_graph(only(tram_track), false)
+    }
+}
+
+fn network_for(n: networks, rt: route_type) -> option<@street_graph> {
+    alt rt {
+        gtfs::bus { some(n.road) }
+        gtfs::rail | gtfs::subway | gtfs::funicular { some(n.rail) }
+        gtfs::tram { some(n.tram) }
+        _ { none }
+    }
+}
+
+/* a generated shape, how far along it each stop of its pattern lies, and
+   the trips following it */
+type trip_shape = {
+    shape: shape,
+    stop_dists: [ float ],
+    trip_ids: [ str ]
+};
+
+/* the shape for a pattern, with the pattern's id */
+fn pattern_shape(feed: feed, n: networks, pat: @pattern) -> trip_shape {
+    let route = feed.routes.get(pat.route_id);
+    let stops = vec::map(pat.stop_ids) { |id| feed.stops.get(id).pt };
+    let mut pts = [ stops[0] ];
+    /* index in pts of each stop */
+    let mut stop_pts = [ 0u ];
+    let graph = network_for(n, route.route_type);
+    let snapped = vec::map(stops) { |pt|
+        alt graph {
+            some(g) { snap(g, pt, snap_metres) }
+            none { none }
+        }
+    };
+    let mut i = 1u;
+    while i < vec::len(stops) {
+        /* a detour much longer than the direct line isn't believable */
+        let limit = 3. * haversine(stops[i - 1u], stops[i]) + 1000.;
+        let path = alt (graph, snapped[i - 1u], snapped[i]) {
+            (some(g), some((a, _)), some((b, _))) { path_between(g, a, b, limit) }
+            _ { none }
+        };
+        alt path {
+            some(nodes) {
+                let g = option::get(graph);
+                for vec::each(nodes) { |node| pts += [ g.pts[node] ]; }
+            }
+            none {}
+        }
+        stop_pts += [ vec::len(pts) ];
+        pts += [ stops[i] ];
+        i += 1u;
+    }
+
+    let mut dists = [ 0. ];
+    let mut travelled = 0.;
+    let mut j = 1u;
+    while j < vec::len(pts) {
+        travelled += haversine(pts[j - 1u], pts[j]);
+        dists += [ travelled ];
+        j += 1u;
+    }
+    {
+        shape: { id: pat.id, pts: pts, dist_traveled: dists },
+        stop_dists: vec::map(stop_pts) { |k| dists[k] },
+        trip_ids: pat.trip_ids
+    }
+}
+
+/* a shape for every pattern of the given trips */
+fn generate_shapes(feed: feed, n: networks, trip_ids: [ str ]) -> [ trip_shape ] {
+    vec::map(find_patterns(feed, trip_ids)) { |pat| pattern_shape(feed, n, pat) }
+}
+
+fn dist_str(d: float) -> str {
+    #fmt("%.1f", d)
+}
+
+/* the shapes, as a GTFS shapes.txt */
+fn shapes_csv(shapes: [ trip_shape ]) -> str {
+    let mut res = csv_row([ "shape_id", "shape_pt_lat", "shape_pt_lon", "shape_pt_sequence",
+                            "shape_dist_traveled" ]) + "\n";
+    for vec::each(shapes) { |ts|
+        let s = ts.shape;
+        for vec::eachi(s.pts) { |i, pt|
+            res += csv_row([ s.id, #fmt("%.6f", pt.lat), #fmt("%.6f", pt.lon),
+                             uint::str(i + 1u), dist_str(s.dist_traveled[i]) ]) + "\n";
+        }
+    }
+    ret res;
+}
+
+fn sorted_keys<V: copy>(m: map::hashmap<str, V>) -> [ str ] {
+    let mut ids = [];
+    for m.each_key() { |k| ids += [ k ]; }
+    sort::merge_sort({|a, b| a <= b }, ids)
+}
+
+fn opt_str(s: option<str>) -> str {
+    alt s {
+        some(v) { v }
+        none { "" }
+    }
+}
+
+fn marshal_str(m: option<marshal>) -> str {
+    alt m {
+        some(gtfs::scheduled) { "0" }
+        some(gtfs::nopickup) { "1" }
+        some(gtfs::phoneahead) { "2" }
+        some(gtfs::coordinatewithdriver) { "3" }
+        none { "" }
+    }
+}
+
+/* the feed's trips as a GTFS trips.txt, with shape_id set for the trips
+   given a shape. only the columns the loader reads are kept */
+fn trips_csv(feed: feed, shapes: [ trip_shape ]) -> str {
+    let shape_of : map::hashmap<str, str> = map::str_hash();
+    for vec::each(shapes) { |ts|
+        for vec::each(ts.trip_ids) { |trip_id| shape_of.insert(trip_id, ts.shape.id); }
+    }
+    let mut res = csv_row([ "route_id", "service_id", "trip_id", "trip_headsign",
+                            "trip_short_name", "direction_id", "block_id", "shape_id" ]) + "\n";
+    for vec::each(sorted_keys(feed.trips)) { |trip_id|
+        let trip = feed.trips.get(trip_id);
+        let shape_id = alt shape_of.find(trip_id) {
+            some(id) { some(id) }
+            none { trip.shape_id }
+        };
+        let direction = alt trip.direction {
+            some(gtfs::oneway) { "0" }
+            some(gtfs::theotherway) { "1" }
+            none { "" }
+        };
+        res += csv_row([ trip.route_id, trip.service_id, trip.id, opt_str(trip.headsign),
+                         opt_str(trip.short_name), direction, opt_str(trip.block_id),
+                         opt_str(shape_id) ]) + "\n";
+    }
+    ret res;
+}
+
+/* the feed's stop times as a GTFS stop_times.txt, with each stop's
+   distance along its trip's generated shape. trips without one keep the
+   feed's own distances */
+fn stop_times_csv(feed: feed, shapes: [ trip_shape ]) -> str {
+    let dists_of : map::hashmap<str, [ float ]> = map::str_hash();
+    for vec::each(shapes) { |ts|
+        for vec::each(ts.trip_ids) { |trip_id| dists_of.insert(trip_id, ts.stop_dists); }
+    }
+    let mut res = csv_row([ "trip_id", "arrival_time", "departure_time", "stop_id",
+                            "stop_sequence", "stop_headsign", "pickup_type", "drop_off_type",
+                            "shape_dist_traveled" ]) + "\n";
+    for vec::each(sorted_keys(feed.stop_times)) { |trip_id|
+        let dists = dists_of.find(trip_id);
+        for vec::eachi(vec::from_mut(feed.stop_times.get(trip_id))) { |i, st|
+            let dist = alt dists {
+                some(d) { dist_str(d[i]) }
+                none {
+                    alt st.shape_dist_travelled {
+                        some(d) { float::to_str(d, 6u) }
+                        none { "" }
+                    }
+                }
+            };
+            res += csv_row([ st.trip_id, time_format(st.arrival_time),
+                             time_format(st.departure_time), st.stop_id,
+                             uint::str(st.sequence), opt_str(st.headsign),
+                             marshal_str(st.pickup_type), marshal_str(st.drop_off_type),
+                             dist ]) + "\n";
+        }
+    }
+    ret res;
+}
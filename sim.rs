@@ -0,0 +1,64 @@
+import pqueue::{pqueue, new_pqueue, push, pop, peek_key};
+
+export sim, new_sim, now, schedule, schedule_after, stop, pending, run;
+
+/*
+ * a discrete-event scheduler. events of any type the caller likes are
+ * queued by time; anything holding the sim can schedule more while it
+ * runs. events at the same time are handled in the order scheduled.
+ */
+
+type sim<E> = @{
+    queue: pqueue<E>,
+    mut now: uint,
+    mut stopped: bool
+};
+
+fn new_sim<E: copy>(start: uint) -> sim<E> {
+    @{ queue: new_pqueue(), mut now: start, mut stopped: false }
+}
+
+fn now<E: copy>(s: sim<E>) -> uint {
+    s.now
+}
+
+fn pending<E: copy>(s: sim<E>) -> uint {
+    pqueue::len(s.queue)
+}
+
+/* queues `ev` for `time`, which can't be before now */
+fn schedule<E: copy>(s: sim<E>, time: uint, ev: E) {
+    if time < s.now {
+        fail(#fmt("sim: event scheduled for %u, but it's already %u", time, s.now));
+    }
+    push(s.queue, time, ev);
+}
+
+fn schedule_after<E: copy>(s: sim<E>, delay: uint, ev: E) {
+    schedule(s, s.now + delay, ev);
+}
+
+/* makes `run` return once the current event has been handled */
+fn stop<E: copy>(s: sim<E>) {
+    s.stopped = true;
+}
+
+/* hands each event to `f` in time order, until the queue empties, `stop`
+   is called, or the next event is after `until`. returns the number of
+   events handled */
+fn run<E: copy>(s: sim<E>, until: option<uint>, f: fn(uint, E)) -> uint {
+    let mut handled = 0u;
+    s.stopped = false;
+    while !s.stopped {
+        alt (peek_key(s.queue), until) {
+            (none, _) { break; }
+            (some(t), some(u)) if t > u { s.now = u; break; }
+            _ {}
+        }
+        let (time, ev) = option::get(pop(s.queue));
+        s.now = time;
+        f(time, ev);
+        handled += 1u;
+    }
+    ret handled;
+}
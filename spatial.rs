@@ -0,0 +1,161 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import std::sort;
+import gtfs::{stop, point, rectangle};
+import geom::{metres_per_degree, haversine, rect_expand, rect_contains};
+
+export stop_grid, build_grid, within_radius, nearest, within_rect;
+
+/*
+ * a uniform lat/lon grid over stops. only occupied cells are stored, so
+ * a country-wide feed costs no more than a city. longitudes aren't wrapped,
+ * so feeds crossing the antimeridian will find too few neighbours.
+ */
+
+/* stops are bucketed into cells roughly this many metres high */
+const cell_metres : float = 500.;
+
+type stop_grid = {
+    /* cell size in degrees */
+    cell: float,
+    cells: map::hashmap<str, [ mut @stop ]>,
+    bounds: rectangle,
+    count: uint,
+    /* the rows and columns holding any stops, (min, max) */
+    rows: (int, int),
+    cols: (int, int)
+};
+
+fn cell_key(row: int, col: int) -> str {
+    #fmt("%d,%d", row, col)
+}
+
+fn cell_of(grid: stop_grid, pt: point) -> (int, int) {
+    (float::floor(pt.lat / grid.cell) as int, float::floor(pt.lon / grid.cell) as int)
+}
+
+fn build_grid(stops: [ @stop ], bounds: rectangle) -> stop_grid {
+    let cell = cell_metres / metres_per_degree();
+    /* empty ranges until there are stops */
+    let mut rows = (1, 0);
+    let mut cols = (1, 0);
+    for vec::eachi(stops) { |i, stop|
+        let row = float::floor(stop.pt.lat / cell) as int;
+        let col = float::floor(stop.pt.lon / cell) as int;
+        if i == 0u {
+            rows = (row, row);
+            cols = (col, col);
+        } else {
+            let (rmin, rmax) = rows;
+            let (cmin, cmax) = cols;
+            rows = (int::min(rmin, row), int::max(rmax, row));
+            cols = (int::min(cmin, col), int::max(cmax, col));
+        }
+    }
+    let grid = {
+        cell: cell,
+        cells: map::str_hash(),
+        bounds: bounds,
+        count: vec::len(stops),
+        rows: rows,
+        cols: cols
+    };
+    for vec::each(stops) { |stop|
+        let (row, col) = cell_of(grid, stop.pt);
+        let key = cell_key(row, col);
+        let mut members = if grid.cells.contains_key(key) {
+            grid.cells.get(key)
+        } else {
+            [mut]
+        };
+        members += [ stop ];
+        grid.cells.insert(key, members);
+    }
+    ret grid;
+}
+
+/* calls `f` with each stop in the cells overlapping the given lat/lon
+   window; only the part of the window holding stops is looked at */
+fn each_in_window(grid: stop_grid, sw: point, ne: point, f: fn(@stop)) {
+    let (row_lo, col_lo) = cell_of(grid, sw);
+    let (row_hi, col_hi) = cell_of(grid, ne);
+    let (rmin, rmax) = grid.rows;
+    let (cmin, cmax) = grid.cols;
+    let row_min = int::max(row_lo, rmin);
+    let row_max = int::min(row_hi, rmax);
+    let col_min = int::max(col_lo, cmin);
+    let col_max = int::min(col_hi, cmax);
+    let mut row = row_min;
+    while row <= row_max {
+        let mut col = col_min;
+        while col <= col_max {
+            alt grid.cells.find(cell_key(row, col)) {
+                some(members) {
+                    for vec::each(members) { |stop| f(stop); }
+                }
+                none {}
+            }
+            col += 1;
+        }
+        row += 1;
+    }
+}
+
+fn sort_by_distance(v: [ mut (@stop, float) ]) {
+    sort::quick_sort3({|a, b|
+        let (_, da) = a;
+        let (_, db) = b;
+        da < db
+    }, {|a, b|
+        let (_, da) = a;
+        let (_, db) = b;
+        da == db
+    }, v);
+}
+
+/* stops within `metres` of `pt`, nearest first, with their distances */
+fn within_radius(grid: stop_grid, pt: point, metres: float) -> [ (@stop, float) ] {
+    let window = rect_expand({ sw: pt, ne: pt }, metres);
+    let mut res = [mut];
+    each_in_window(grid, window.sw, window.ne) { |stop|
+        let d = haversine(pt, stop.pt);
+        if d <= metres {
+            res += [ (stop, d) ];
+        }
+    };
+    sort_by_distance(res);
+    ret vec::from_mut(res);
+}
+
+/* the `k` stops nearest to `pt`, nearest first, with their distances */
+fn nearest(grid: stop_grid, pt: point, k: uint) -> [ (@stop, float) ] {
+    if k == 0u || grid.count == 0u {
+        ret [];
+    }
+    /* widen the search until it holds k stops; every stop nearer than the
+       search radius has then been seen. past the far corner of the feed
+       there's nothing more to find. */
+    let limit = float::fmax(haversine(pt, grid.bounds.sw), haversine(pt, grid.bounds.ne))
+        + haversine(grid.bounds.sw, grid.bounds.ne);
+    let mut radius = cell_metres;
+    loop {
+        let found = within_radius(grid, pt, radius);
+        if vec::len(found) >= k || radius > limit {
+            ret vec::slice(found, 0u, uint::min(k, vec::len(found)));
+        }
+        radius *= 2.;
+    }
+}
+
+/* stops inside the rectangle */
+fn within_rect(grid: stop_grid, rect: rectangle) -> [ @stop ] {
+    let mut res = [];
+    each_in_window(grid, rect.sw, rect.ne) { |stop|
+        if rect_contains(rect, stop.pt) {
+            res += [ stop ];
+        }
+    };
+    ret res;
+}
@@ -0,0 +1,291 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::{feed, feedaccess, stop, point};
+import geom::{metres_per_degree, haversine, rect_expand};
+import osm::{osm, tag_value};
+import transfer::{transfers, new_transfers, add_transfer, station_transfers,
+                  walking, walk_secs};
+import pqueue::{new_pqueue, push, pop};
+
+export street_graph, build_graph, snap, distances_from, path_between,
+       streets, street_network, street_walks, street_transfers;
+
+/*
+ * a routable graph over OSM ways, and walking times along it. stops and
+ * other points join the graph at their nearest node; anything too far
+ * from the streets falls back to the straight-line estimate.
+ */
+
+/* nodes are bucketed into cells roughly this many metres high */
+const cell_metres : float = 250.;
+/* a point further than this from every node isn't on the network */
+const snap_metres : float = 200.;
+
+type street_graph = {
+    ids: [ int ],
+    pts: [ point ],
+    /* (node, metres) for each way out of each node */
+    edges: [ [ (uint, float) ] ],
+    /* cell size in degrees */
+    cell: float,
+    cells: map::hashmap<str, [ uint ]>
+};
+
+fn cell_key(row: int, col: int) -> str {
+    #fmt("%d,%d", row, col)
+}
+
+fn cell_of(cell: float, pt: point) -> (int, int) {
+    (float::floor(pt.lat / cell) as int, float::floor(pt.lon / cell) as int)
+}
+
+/* the graph of the ways in `o`; with `oneway`, ways tagged one way can
+   only be followed in that direction */
+fn build_graph(o: osm, oneway: bool) -> @street_graph {
+    let index : map::hashmap<int, uint> = map::int_hash();
+    let mut ids = [];
+    let mut pts = [];
+    for vec::each(o.ways) { |w|
+        for vec::each(w.refs) { |r|
+            if !index.contains_key(r) {
+                alt o.nodes.find(r) {
+                    some(pt) {
+                        index.insert(r, vec::len(ids));
+                        ids += [ r ];
+                        pts += [ pt ];
+                    }
+                    none {}
+                }
+            }
+        }
+    }
+
+    let mut edges = vec::to_mut(vec::from_elem(vec::len(ids), []));
+    for vec::each(o.ways) { |w|
+        let dir = if !oneway {
+            0
+        } else {
+            alt tag_value(w.tags, "oneway") {
+                some("yes") | some("true") | some("1") { 1 }
+                some("-1") | some("reverse") { -1 }
+                _ { 0 }
+            }
+        };
+        let mut i = 1u;
+        while i < vec::len(w.refs) {
+            alt (index.find(w.refs[i - 1u]), index.find(w.refs[i])) {
+                (some(a), some(b)) if a != b {
+                    let metres = haversine(pts[a], pts[b]);
+                    if dir >= 0 { edges[a] += [ (b, metres) ]; }
+                    if dir <= 0 { edges[b] += [ (a, metres) ]; }
+                }
+                _ {}
+            }
+            i += 1u;
+        }
+    }
+
+    let cell = cell_metres / metres_per_degree();
+    let cells = map::str_hash();
+    for vec::eachi(pts) { |n, pt|
+        let (row, col) = cell_of(cell, pt);
+        let key = cell_key(row, col);
+        let members = alt cells.find(key) {
+            some(v) { v }
+            none { [] }
+        };
+        cells.insert(key, members + [ n ]);
+    }
+    ret @{ ids: ids, pts: pts, edges: vec::from_mut(edges), cell: cell, cells: cells };
+}
+
+/* the node nearest `pt`, and how far away it is, if any is within
+   `limit` metres */
+fn snap(g: @street_graph, pt: point, limit: float) -> option<(uint, float)> {
+    let window = rect_expand({ sw: pt, ne: pt }, limit);
+    let (row_min, col_min) = cell_of(g.cell, window.sw);
+    let (row_max, col_max) = cell_of(g.cell, window.ne);
+    let mut best = none;
+    let mut best_metres = limit;
+    let mut row = row_min;
+    while row <= row_max {
+        let mut col = col_min;
+        while col <= col_max {
+            alt g.cells.find(cell_key(row, col)) {
+                some(members) {
+                    for vec::each(members) { |n|
+                        let d = haversine(pt, g.pts[n]);
+                        if d <= best_metres {
+                            best = some(n);
+                            best_metres = d;
+                        }
+                    }
+                }
+                none {}
+            }
+            col += 1;
+        }
+        row += 1;
+    }
+    alt best {
+        some(n) { some((n, best_metres)) }
+        none { none }
+    }
+}
+
+/* shortest distances along the graph from the given (node, metres so far)
+   sources to every node within `limit` metres */
+fn distances_from(g: @street_graph, sources: [ (uint, float) ], limit: float) -> map::hashmap<uint, float> {
+    let dist : map::hashmap<uint, float> = map::uint_hash();
+    let q = new_pqueue::<uint>();
+    /* the queue's keys are whole millimetres */
+    let key = { |metres: float| (metres * 1000.) as uint };
+    for vec::each(sources) { |s|
+        let (n, metres) = s;
+        if metres <= limit && (!dist.contains_key(n) || metres < dist.get(n)) {
+            dist.insert(n, metres);
+            push(q, key(metres), n);
+        }
+    }
+    let done : map::hashmap<uint, ()> = map::uint_hash();
+    loop {
+        let n = alt pop(q) {
+            some((_, n)) { n }
+            none { break; }
+        };
+        if done.contains_key(n) {
+            cont;
+        }
+        done.insert(n, ());
+        let here = dist.get(n);
+        for vec::each(g.edges[n]) { |e|
+            let (m, metres) = e;
+            let there = here + metres;
+            if there <= limit && (!dist.contains_key(m) || there < dist.get(m)) {
+                dist.insert(m, there);
+                push(q, key(there), m);
+            }
+        }
+    }
+    ret dist;
+}
+
+/* the nodes on a shortest path from `from` to `to`, both included, if
+   there's one no longer than `limit` metres */
+fn path_between(g: @street_graph, from: uint, to: uint, limit: float) -> option<[ uint ]> {
+    let dist : map::hashmap<uint, float> = map::uint_hash();
+    let prev : map::hashmap<uint, uint> = map::uint_hash();
+    let done : map::hashmap<uint, ()> = map::uint_hash();
+    let q = new_pqueue::<uint>();
+    dist.insert(from, 0.);
+    push(q, 0u, from);
+    loop {
+        let n = alt pop(q) {
+            some((_, n)) { n }
+            none { ret none; }
+        };
+        if n == to {
+            break;
+        }
+        if done.contains_key(n) {
+            cont;
+        }
+        done.insert(n, ());
+        let here = dist.get(n);
+        for vec::each(g.edges[n]) { |e|
+            let (m, metres) = e;
+            let there = here + metres;
+            if there <= limit && (!dist.contains_key(m) || there < dist.get(m)) {
+                dist.insert(m, there);
+                prev.insert(m, n);
+                push(q, (there * 1000.) as uint, m);
+            }
+        }
+    }
+    let mut path = [ to ];
+    let mut n = to;
+    while n != from {
+        n = prev.get(n);
+        path += [ n ];
+    }
+    ret some(vec::reversed(path));
+}
+
+type streets = @{
+    graph: @street_graph,
+    /* where each stop joins the graph, and how far it is from it */
+    stop_nodes: map::hashmap<str, (uint, float)>
+};
+
+/* the walkable network of `o`, with the feed's stops snapped onto it */
+fn street_network(feed: feed, o: osm) -> streets {
+    let g = build_graph(o, false);
+    let stop_nodes = map::str_hash();
+    for feed.stops.each_value() { |stop|
+        alt snap(g, stop.pt, snap_metres) {
+            some(sn) { stop_nodes.insert(stop.id, sn); }
+            none {}
+        }
+    }
+    ret @{ graph: g, stop_nodes: stop_nodes };
+}
+
+/* walking times, in seconds, from `pt` to the stops in reach. `w.radius`
+   still bounds the straight-line search; along the streets a walk may be
+   up to `w.detour` times that */
+fn walks_from(feed: feed, s: streets, pt: point, start: option<(uint, float)>, w: walking) -> [ (@stop, uint) ] {
+    let near = feed.stops_within_radius(pt, w.radius);
+    let (node, offset) = alt start {
+        some(sn) { sn }
+        none {
+            ret vec::map(near) { |sd|
+                let (stop, metres) = sd;
+                (stop, walk_secs(w, metres))
+            };
+        }
+    };
+    let limit = w.radius * w.detour;
+    let dist = distances_from(s.graph, [ (node, offset) ], limit);
+    let mut res = [];
+    for vec::each(near) { |sd|
+        let (stop, metres) = sd;
+        alt s.stop_nodes.find(stop.id) {
+            some((n, stop_offset)) {
+                alt dist.find(n) {
+                    some(d) if d + stop_offset <= limit {
+                        res += [ (stop, float::ceil((d + stop_offset) / w.speed) as uint) ];
+                    }
+                    _ {}
+                }
+            }
+            /* the stop isn't near the streets, so guess */
+            none { res += [ (stop, walk_secs(w, metres)) ]; }
+        }
+    }
+    ret res;
+}
+
+/* walking times along the streets from `pt` to the stops in reach */
+fn street_walks(feed: feed, s: streets, pt: point, w: walking) -> [ (@stop, uint) ] {
+    walks_from(feed, s, pt, snap(s.graph, pt, snap_metres), w)
+}
+
+/* like transfer::walking_transfers, walking the streets rather than
+   straight lines */
+fn street_transfers(feed: feed, s: streets, w: walking, station_cap: option<uint>) -> transfers {
+    let t = alt station_cap {
+        some(secs) { station_transfers(feed, secs) }
+        none { new_transfers() }
+    };
+    for feed.stops.each_value() { |stop|
+        for vec::each(walks_from(feed, s, stop.pt, s.stop_nodes.find(stop.id), w)) { |walk|
+            let (other, secs) = walk;
+            if other.id != stop.id {
+                add_transfer(t, stop.id, other.id, secs);
+            }
+        }
+    }
+    ret t;
+}
@@ -0,0 +1,107 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import gtfs::{feed, date};
+
+export test_feed, test_date, add_stop, add_trip;
+
+/*
+ * tiny feeds built by hand, for the tests. everything belongs to agency
+ * "a", runs on service "s" every day of 2012, and routes are buses.
+ */
+
+fn test_feed() -> feed {
+    let f = {
+        agencies: map::str_hash(),
+        stops: map::str_hash(),
+        routes: map::str_hash(),
+        trips: map::str_hash(),
+        stop_times: map::str_hash(),
+        calendars: map::str_hash(),
+        calendar_dates: map::str_hash(),
+        shapes: map::str_hash(),
+        stop_grid_cache: @{ mut grid: none }
+    };
+    f.agencies.insert("a", @{
+        id: "a",
+        name: "a",
+        url: "",
+        timezone: "UTC",
+        lang: none,
+        phone: none,
+        fare_url: none
+    });
+    f.calendars.insert("s", @{
+        service_id: "s",
+        weekdays: [ gtfs::monday, gtfs::tuesday, gtfs::wednesday, gtfs::thursday,
+                    gtfs::friday, gtfs::saturday, gtfs::sunday ],
+        start_date: { day: 1u, month: 1u, year: 2012u },
+        end_date: { day: 31u, month: 12u, year: 2012u }
+    });
+    ret f;
+}
+
+/* a monday the service runs */
+fn test_date() -> date {
+    { day: 4u, month: 6u, year: 2012u }
+}
+
+fn add_stop(f: feed, id: str, lat: float, lon: float) {
+    f.stops.insert(id, @{
+        id: id,
+        code: none,
+        name: id,
+        pt: { lat: lat, lon: lon },
+        desc: none,
+        zone_id: none,
+        url: none,
+        location_type: none,
+        parent_station: none,
+        timezone: none
+    });
+}
+
+/* a trip calling at each (stop_id, arrival, departure) in turn, on the
+   route given, which is made if need be */
+fn add_trip(f: feed, route_id: str, trip_id: str, calls: [ (str, uint, uint) ]) {
+    if !f.routes.contains_key(route_id) {
+        f.routes.insert(route_id, @{
+            id: route_id,
+            agency_id: "a",
+            short_name: route_id,
+            long_name: "",
+            desc: none,
+            route_type: gtfs::bus,
+            url: none,
+            color: none,
+            text_color: none
+        });
+    }
+    f.trips.insert(trip_id, @{
+        id: trip_id,
+        route_id: route_id,
+        service_id: "s",
+        headsign: none,
+        short_name: none,
+        direction: none,
+        block_id: none,
+        shape_id: none
+    });
+    let mut times = [ mut ];
+    for vec::eachi(calls) { |i, c|
+        let (stop_id, arr, dep) = c;
+        times += [ mut @{
+            trip_id: trip_id,
+            arrival_time: arr,
+            departure_time: dep,
+            stop_id: stop_id,
+            sequence: i + 1u,
+            headsign: none,
+            pickup_type: none,
+            drop_off_type: none,
+            shape_dist_travelled: none
+        } ];
+    }
+    f.stop_times.insert(trip_id, times);
+}
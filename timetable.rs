@@ -0,0 +1,233 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import std::sort;
+import gtfs::{feed, feedaccess, route, stop, trip, date, direction,
+              weekday_of, time_format};
+import report::{csv_row, html_escape};
+import patterns::{find_patterns};
+
+export timetable, route_timetable, format_text, format_csv, format_html;
+
+/*
+ * a printable timetable for one route: stops down the side, trips across
+ * the top. stops are put in an order every trip agrees with, so branches
+ * get their own rows and short-running trips leave gaps.
+ */
+
+type timetable = {
+    route: @route,
+    direction: option<direction>,
+    date: date,
+    stops: [ @stop ],
+    trips: [ @trip ],
+    /* times[row][column]: departure time, or arrival at a trip's last stop */
+    times: [ [ option<uint> ] ]
+};
+
+/* inserts the stops of `seq` into `master` so that `seq` becomes a
+   subsequence of it, reusing rows where the orders agree */
+fn merge_sequence(master: [ str ], seq: [ str ]) -> [ str ] {
+    let mut res = [];
+    let mut pos = 0u;
+    for vec::each(seq) { |stop_id|
+        let mut found = none;
+        let mut i = pos;
+        while i < vec::len(master) {
+            if master[i] == stop_id {
+                found = some(i);
+                break;
+            }
+            i += 1u;
+        }
+        alt found {
+            some(i) {
+                res += vec::slice(master, pos, i + 1u);
+                pos = i + 1u;
+            }
+            none {
+                res += [ stop_id ];
+            }
+        }
+    }
+    res += vec::slice(master, pos, vec::len(master));
+    ret res;
+}
+
+/* row of each stop in `seq`, matching as early as possible */
+fn rows_for_sequence(master: [ str ], seq: [ str ]) -> [ uint ] {
+    let mut rows = [];
+    let mut pos = 0u;
+    for vec::each(seq) { |stop_id|
+        while master[pos] != stop_id {
+            pos += 1u;
+        }
+        rows += [ pos ];
+        pos += 1u;
+    }
+    ret rows;
+}
+
+fn route_timetable(feed: feed, route_id: str, direction: option<direction>, date: date) -> timetable {
+    let route = feed.routes.get(route_id);
+    let service_ids = feed.active_service_ids(weekday_of(date), date);
+    let trips = vec::filter(feed.lookup_trips(feed.trip_ids_for_service_ids(service_ids))) { |trip|
+        trip.route_id == route_id && feed.stop_times.contains_key(trip.id) &&
+            (option::is_none(direction) || trip.direction == direction)
+    };
+    let mut runs = [mut];
+    for vec::each(trips) { |trip|
+        let times = feed.stop_times.get(trip.id);
+        runs += [ (trip, vec::from_mut(times)) ];
+    }
+    /* columns in order of departure from the first stop */
+    sort::quick_sort3({|a, b|
+        let (ta, sa) = a;
+        let (tb, sb) = b;
+        sa[0].departure_time < sb[0].departure_time ||
+            (sa[0].departure_time == sb[0].departure_time && ta.id < tb.id)
+    }, {|a, b|
+        let (ta, sa) = a;
+        let (tb, sb) = b;
+        sa[0].departure_time == sb[0].departure_time && ta.id == tb.id
+    }, runs);
+
+    /* build the row order from the trips' patterns, longest first so that
+       the full-length pattern sets out the main line */
+    let pats = find_patterns(feed, vec::map(trips) { |t| t.id });
+    let mut seqs = vec::to_mut(vec::map(pats) { |p| p.stop_ids });
+    sort::quick_sort3({|a, b|
+        vec::len(a) > vec::len(b) || (vec::len(a) == vec::len(b) && a < b)
+    }, {|a, b| a == b }, seqs);
+    let mut master = [];
+    for vec::each(seqs) { |seq|
+        master = merge_sequence(master, seq);
+    }
+
+    let ncols = vec::len(runs);
+    let mut cells = vec::to_mut(vec::from_elem(vec::len(master) * ncols, none));
+    for vec::eachi(runs) { |col, r|
+        let (_, times) = r;
+        let rows = rows_for_sequence(master, vec::map(times) { |st| st.stop_id });
+        let last = vec::len(times) - 1u;
+        for vec::eachi(times) { |i, st|
+            cells[rows[i] * ncols + col] = some(if i == last { st.arrival_time } else { st.departure_time });
+        }
+    }
+    let times = vec::from_fn(vec::len(master)) { |row|
+        vec::from_fn(ncols) { |col| cells[row * ncols + col] }
+    };
+    {
+        route: route,
+        direction: direction,
+        date: date,
+        stops: feed.lookup_stops(master),
+        trips: vec::map(vec::from_mut(runs)) { |r| let (trip, _) = r; trip },
+        times: times
+    }
+}
+
+fn cell(t: option<uint>) -> str {
+    alt t {
+        some(secs) { str::slice(time_format(secs), 0u, 5u) }
+        none { "" }
+    }
+}
+
+fn trip_label(trip: @trip) -> str {
+    alt trip.short_name {
+        some(s) if s != "" { s }
+        _ { trip.id }
+    }
+}
+
+fn title(tt: timetable) -> str {
+    #fmt("%s %s (%s)", tt.route.short_name, tt.route.long_name, gtfs::date_format(tt.date))
+}
+
+/* `s` padded with spaces to `width` characters, on the left or right */
+fn pad_to(s: str, width: uint, left: bool) -> str {
+    let mut fill = "";
+    let mut n = str::char_len(s);
+    while n < width {
+        fill += " ";
+        n += 1u;
+    }
+    if left { fill + s } else { s + fill }
+}
+
+/* stops down the side, a column for each trip headed by its label */
+fn format_text(tt: timetable) -> str {
+    let mut width = 0u;
+    for vec::each(tt.stops) { |stop|
+        width = uint::max(width, str::char_len(stop.name));
+    }
+    let columns = vec::map(tt.trips) { |trip| uint::max(5u, str::char_len(trip_label(trip))) };
+    let mut res = title(tt) + "\n";
+    let mut header = pad_to("", width, false);
+    for vec::eachi(tt.trips) { |i, trip|
+        header += "  " + pad_to(trip_label(trip), columns[i], true);
+    }
+    res += header + "\n";
+    for vec::eachi(tt.stops) { |row, stop|
+        let mut line = pad_to(stop.name, width, false);
+        for vec::eachi(tt.times[row]) { |i, t|
+            line += "  " + pad_to(alt t {
+                some(_) { cell(t) }
+                none { "-" }
+            }, columns[i], true);
+        }
+        res += line + "\n";
+    }
+    ret res;
+}
+
+fn format_csv(tt: timetable) -> str {
+    let mut res = csv_row([ "stop_id", "stop_name" ] + vec::map(tt.trips) { |t| trip_label(t) }) + "\n";
+    for vec::eachi(tt.stops) { |row, stop|
+        res += csv_row([ stop.id, stop.name ] + vec::map(tt.times[row]) { |t| cell(t) }) + "\n";
+    }
+    ret res;
+}
+
+fn format_html(tt: timetable) -> str {
+    let mut res = "<table class=\"timetable\">\n";
+    res += "<caption>" + html_escape(title(tt)) + "</caption>\n";
+    res += "<thead><tr><th></th>";
+    for vec::each(tt.trips) { |trip|
+        res += "<th>" + html_escape(trip_label(trip)) + "</th>";
+    }
+    res += "</tr></thead>\n<tbody>\n";
+    for vec::eachi(tt.stops) { |row, stop|
+        res += "<tr><th>" + html_escape(stop.name) + "</th>";
+        for vec::each(tt.times[row]) { |t|
+            res += "<td>" + cell(t) + "</td>";
+        }
+        res += "</tr>\n";
+    }
+    res += "</tbody>\n</table>\n";
+    ret res;
+}
+
+#[cfg(test)]
+mod tests {
+    import timetable::{route_timetable};
+    import testfeed::{test_feed, test_date, add_stop, add_trip};
+
+    /* one trip by way of b and one by way of c: both get their rows, and
+       each trip leaves a gap in the other's */
+    #[test]
+    fn branches_get_their_own_rows() {
+        let f = test_feed();
+        for vec::each([ "a", "b", "c", "d" ]) { |id| add_stop(f, id, 0., 0.); }
+        add_trip(f, "r", "via-b", [ ("a", 28800u, 28800u), ("b", 29100u, 29100u), ("d", 29400u, 29400u) ]);
+        add_trip(f, "r", "via-c", [ ("a", 30600u, 30600u), ("c", 30900u, 30900u), ("d", 31200u, 31200u) ]);
+        let tt = route_timetable(f, "r", none, test_date());
+        assert(vec::map(tt.stops) { |s| s.id } == [ "a", "c", "b", "d" ]);
+        assert(vec::map(tt.trips) { |t| t.id } == [ "via-b", "via-c" ]);
+        assert(tt.times[1] == [ none, some(30900u) ]);
+        assert(tt.times[2] == [ some(29100u), none ]);
+        assert(tt.times[3] == [ some(29400u), some(31200u) ]);
+    }
+}
@@ -0,0 +1,206 @@
+use std;
+import std::map;
+import std::map::{map};
+import map::hashmap;
+import std::sort;
+import gtfs::{feed, feedaccess, stop, point, file_iter};
+import geom::{haversine};
+
+export transfer, transfers, new_transfers, add_transfer, paths_from, merge_transfers,
+       load_feed_transfers,
+       station_transfers, walking_transfers, walking, default_walking, walk_secs,
+       crow_walks, station_walk, min_change;
+
+/*
+ * footpaths between stops, keyed on the stop.id walked from. routing and
+ * simulation take one of these, however it was built: estimated, along the
+ * streets, or from the feed's own transfers.txt.
+ */
+
+type transfer = {
+    to: str,
+    secs: uint
+};
+
+/* the same footpath can be added more than once; the shortest counts */
+type transfers = map::hashmap<str, @{ mut paths: [ transfer ] }>;
+
+/* walking is estimated from the straight-line distance, stretched by a
+   detour factor for the street network */
+type walking = {
+    /* metres per second */
+    speed: float,
+    detour: float,
+    /* don't walk further than this, in straight-line metres */
+    radius: float
+};
+
+fn default_walking() -> walking {
+    { speed: 1.3, detour: 1.3, radius: 800. }
+}
+
+/* the defaults the tools share: walking between stops within a station,
+   as a cap for walking_transfers */
+const station_walk : uint = 120u;
+/* and time allowed for changing vehicles at the same stop */
+const min_change : uint = 60u;
+
+fn walk_secs(w: walking, metres: float) -> uint {
+    float::ceil(metres * w.detour / w.speed) as uint
+}
+
+/* the stops in walking distance of `pt`, with the walk in seconds */
+fn crow_walks(feed: feed, w: walking, pt: point) -> [ (@stop, uint) ] {
+    vec::map(feed.stops_within_radius(pt, w.radius)) { |sd|
+        let (stop, metres) = sd;
+        (stop, walk_secs(w, metres))
+    }
+}
+
+fn new_transfers() -> transfers {
+    map::str_hash()
+}
+
+fn add_transfer(t: transfers, from: str, to: str, secs: uint) {
+    alt t.find(from) {
+        some(p) { p.paths += [ { to: to, secs: secs } ]; }
+        none { t.insert(from, @{ mut paths: [ { to: to, secs: secs } ] }); }
+    }
+}
+
+/* the footpaths from a stop, the shortest to each stop it reaches */
+fn paths_from(t: transfers, from: str) -> [ transfer ] {
+    let p = alt t.find(from) {
+        some(p) { p }
+        none { ret []; }
+    };
+    let sorted = sort::merge_sort({|a, b| a.to < b.to || (a.to == b.to && a.secs <= b.secs) }, p.paths);
+    let mut res = [];
+    for vec::each(sorted) { |x|
+        if x.secs != uint::max_value && (vec::len(res) == 0u || res[vec::len(res) - 1u].to != x.to) {
+            res += [ x ];
+        }
+    }
+    ret res;
+}
+
+/* the footpaths in `other` take the place of any in `t` between the same
+   stops; one of uint::max_value seconds takes them away */
+fn merge_transfers(t: transfers, other: transfers) {
+    for other.each() { |from, o|
+        let replaced : map::set<str> = map::str_hash();
+        for vec::each(o.paths) { |x| map::set_add(replaced, x.to); }
+        let mut paths = alt t.find(from) {
+            some(p) { vec::filter(p.paths) { |x| !replaced.contains_key(x.to) } }
+            none { [] }
+        };
+        for vec::each(o.paths) { |x|
+            if x.secs != uint::max_value {
+                paths += [ x ];
+            }
+        }
+        t.insert(from, @{ mut paths: paths });
+    }
+}
+
+/* the footpaths in the feed's transfers.txt, if it has one, to merge over
+   estimated ones. a transfer without a min_transfer_time is estimated as a
+   walk; one that isn't possible (type 3) is uint::max_value seconds.
+   changes at the same stop are left to the router's change time */
+fn load_feed_transfers(feed: feed, dir: str, w: walking) -> transfers {
+    let t = new_transfers();
+    let fname = path::connect(dir, "transfers.txt");
+    if !os::path_exists(fname) {
+        ret t;
+    }
+    enum req { from_stop_id, to_stop_id }
+    enum opt { transfer_type, min_transfer_time }
+    let reqf = [
+        (from_stop_id as uint, "from_stop_id"),
+        (to_stop_id as uint, "to_stop_id")
+            ];
+    let optf = [
+        (transfer_type as uint, "transfer_type"),
+        (min_transfer_time as uint, "min_transfer_time")
+            ];
+    file_iter(fname, reqf, optf) { |row, req, opt|
+        let from = row[req[from_stop_id as uint]];
+        let to = row[req[to_stop_id as uint]];
+        let field = { |o: opt|
+            alt opt[o as uint] {
+                some(n) if row[n] != "" { some(row[n]) }
+                _ { none }
+            }
+        };
+        alt (feed.stops.find(from), feed.stops.find(to)) {
+            (some(a), some(b)) if from != to {
+                let secs = if field(transfer_type) == some("3") {
+                    uint::max_value
+                } else {
+                    alt field(min_transfer_time) {
+                        some(s) {
+                            alt uint::from_str(s) {
+                                some(n) { n }
+                                none { fail(#fmt("%s: bad min_transfer_time: %s", fname, s)) }
+                            }
+                        }
+                        none { walk_secs(w, haversine(a.pt, b.pt)) }
+                    }
+                };
+                add_transfer(t, from, to, secs);
+            }
+            _ {}
+        }
+    };
+    ret t;
+}
+
+/* footpaths taking `secs` both ways between every pair of stops within a
+   station, and between the station and each of its stops */
+fn station_transfers(feed: feed, secs: uint) -> transfers {
+    let t = new_transfers();
+    let children : map::hashmap<str, [ str ]> = map::str_hash();
+    for feed.stops.each_value() { |stop|
+        alt stop.parent_station {
+            some(parent) if parent != "" {
+                let mut v = alt children.find(parent) {
+                    some(v) { v }
+                    none { [] }
+                };
+                v += [ stop.id ];
+                children.insert(parent, v);
+            }
+            _ {}
+        }
+    }
+    for children.each() { |parent, ids|
+        let all = [ parent ] + ids;
+        for vec::each(all) { |a|
+            for vec::each(all) { |b|
+                if a != b {
+                    add_transfer(t, a, b, secs);
+                }
+            }
+        }
+    }
+    ret t;
+}
+
+/* footpaths between every pair of stops within walking radius of each
+   other. with a station cap, stops sharing a station are never more than
+   that many seconds apart, however far apart their coordinates are */
+fn walking_transfers(feed: feed, w: walking, station_cap: option<uint>) -> transfers {
+    let t = alt station_cap {
+        some(secs) { station_transfers(feed, secs) }
+        none { new_transfers() }
+    };
+    for feed.stops.each_value() { |stop|
+        for vec::each(feed.stops_within_radius(stop.pt, w.radius)) { |sd|
+            let (other, metres) = sd;
+            if other.id != stop.id {
+                add_transfer(t, stop.id, other.id, walk_secs(w, metres));
+            }
+        }
+    }
+    ret t;
+}